@@ -0,0 +1,238 @@
+use chrono::{DateTime, Duration as CDuration, Utc};
+use std::collections::HashMap;
+use url::Url;
+
+// ------------ url aggregation ----------------------------------------------
+/// Query parameters that only identify campaigns or clicks, never content.
+const TRACKING_PARAMS: &[&str] = &[
+    "fbclid", "gclid", "dclid", "gbraid", "wbraid", "msclkid", "yclid", "twclid", "igshid",
+    "mc_cid", "mc_eid", "_ga", "_gl", "ref_src",
+];
+
+/// A single page view before aggregation.
+pub struct Visit {
+    pub url: String,
+    pub ts: DateTime<Utc>,
+    pub dwell: Option<CDuration>,
+}
+
+/// Repeated views of the same (normalized) URL collapsed into one line.
+pub struct AggregatedVisit {
+    pub url: String,
+    pub count: usize,
+    pub first: DateTime<Utc>,
+    pub last: DateTime<Utc>,
+    /// Total estimated dwell across all views, if any view had one
+    pub dwell: Option<CDuration>,
+}
+
+fn is_tracking_param(name: &str) -> bool {
+    let name = name.to_ascii_lowercase();
+    name.starts_with("utm_") || TRACKING_PARAMS.contains(&name.as_str())
+}
+
+/// Normalizes a URL so that reloads and tracking variants compare equal:
+/// drops the fragment and tracking parameters, and trailing slashes.
+pub fn normalize_url(raw: &str) -> String {
+    let Ok(mut url) = Url::parse(raw) else {
+        return raw.trim_end_matches('/').to_string();
+    };
+    url.set_fragment(None);
+
+    let kept: Vec<(String, String)> = url
+        .query_pairs()
+        .filter(|(name, _)| !is_tracking_param(name))
+        .map(|(name, value)| (name.into_owned(), value.into_owned()))
+        .collect();
+    if kept.is_empty() {
+        url.set_query(None);
+    } else {
+        url.query_pairs_mut().clear().extend_pairs(kept);
+    }
+
+    let path = url.path().trim_end_matches('/').to_string();
+    url.set_path(&path);
+
+    let normalized = url.to_string();
+    match normalized.strip_suffix('/') {
+        // Url always renders an empty path as "/"
+        Some(stripped) if url.path() == "/" && url.query().is_none() => stripped.to_string(),
+        _ => normalized,
+    }
+}
+
+/// Rolls a URL up to its first `depth` path segments, e.g. depth 2 turns
+/// `https://github.com/owner/repo/issues/42` into `https://github.com/owner/repo/*`.
+pub fn rollup_url(raw: &str, depth: usize) -> String {
+    let normalized = normalize_url(raw);
+    let Ok(url) = Url::parse(&normalized) else {
+        return normalized;
+    };
+    let segments: Vec<&str> = url
+        .path_segments()
+        .map(|s| s.filter(|s| !s.is_empty()).collect())
+        .unwrap_or_default();
+    let origin = url.origin().ascii_serialization();
+    if segments.len() <= depth && url.query().is_none() {
+        return normalized;
+    }
+    let prefix: Vec<&str> = segments.into_iter().take(depth).collect();
+    if prefix.is_empty() {
+        format!("{origin}/*")
+    } else {
+        format!("{origin}/{}/*", prefix.join("/"))
+    }
+}
+
+/// Collapses duplicate views into `url ×N` entries, keeping first-seen order.
+/// With `rollup_depth`, URLs are grouped by path prefix instead of exactly.
+pub fn aggregate_visits(
+    visits: impl IntoIterator<Item = Visit>,
+    rollup_depth: Option<usize>,
+) -> Vec<AggregatedVisit> {
+    let mut aggregated: Vec<AggregatedVisit> = Vec::new();
+    let mut index: HashMap<String, usize> = HashMap::new();
+    for visit in visits {
+        let key = rollup_depth.map_or_else(
+            || normalize_url(&visit.url),
+            |depth| rollup_url(&visit.url, depth),
+        );
+        if let Some(&i) = index.get(&key) {
+            let agg = &mut aggregated[i];
+            agg.count += 1;
+            agg.first = agg.first.min(visit.ts);
+            agg.last = agg.last.max(visit.ts);
+            agg.dwell = match (agg.dwell, visit.dwell) {
+                (Some(a), Some(b)) => Some(a + b),
+                (a, b) => a.or(b),
+            };
+        } else {
+            index.insert(key.clone(), aggregated.len());
+            aggregated.push(AggregatedVisit {
+                url: key,
+                count: 1,
+                first: visit.ts,
+                last: visit.ts,
+                dwell: visit.dwell,
+            });
+        }
+    }
+    aggregated
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{TimeZone, Timelike};
+
+    fn visit(url: &str, minute: u32, dwell: Option<i64>) -> Visit {
+        Visit {
+            url: url.to_string(),
+            ts: Utc
+                .with_ymd_and_hms(2025, 1, 1, 12, minute, 0)
+                .single()
+                .unwrap_or_default(),
+            dwell: dwell.map(CDuration::seconds),
+        }
+    }
+
+    #[test]
+    fn normalize_url_drops_fragments_and_tracking_params() {
+        assert_eq!(
+            normalize_url("https://example.com/page#section"),
+            "https://example.com/page"
+        );
+        assert_eq!(
+            normalize_url("https://example.com/page?utm_source=x&id=7&FBCLID=y&gclid=z&q=a+b"),
+            "https://example.com/page?id=7&q=a+b"
+        );
+        assert_eq!(
+            normalize_url("https://example.com/page?utm_medium=email"),
+            "https://example.com/page"
+        );
+    }
+
+    #[test]
+    fn normalize_url_strips_trailing_slashes_including_the_root() {
+        assert_eq!(
+            normalize_url("https://example.com/docs/"),
+            "https://example.com/docs"
+        );
+        assert_eq!(normalize_url("https://example.com/"), "https://example.com");
+        assert_eq!(normalize_url("https://example.com"), "https://example.com");
+        // The root keeps its slash when a query follows it
+        assert_eq!(
+            normalize_url("https://example.com/?q=1"),
+            "https://example.com/?q=1"
+        );
+        assert_eq!(normalize_url("not a url/"), "not a url");
+    }
+
+    #[test]
+    fn rollup_url_keeps_urls_shorter_than_the_depth() {
+        let url = "https://github.com/owner/repo/issues/42";
+        assert_eq!(rollup_url(url, 0), "https://github.com/*");
+        assert_eq!(rollup_url(url, 1), "https://github.com/owner/*");
+        assert_eq!(rollup_url(url, 2), "https://github.com/owner/repo/*");
+        assert_eq!(
+            rollup_url("https://github.com/owner/", 2),
+            "https://github.com/owner"
+        );
+        assert_eq!(rollup_url("https://github.com/", 0), "https://github.com");
+        // A query means there's more than the path shows
+        assert_eq!(
+            rollup_url("https://github.com/search?q=rust", 2),
+            "https://github.com/search/*"
+        );
+    }
+
+    #[test]
+    fn aggregate_visits_counts_in_first_seen_order() {
+        let visits = vec![
+            visit("https://a.com/x?utm_source=feed", 5, Some(30)),
+            visit("https://b.com/", 1, None),
+            visit("https://a.com/x#top", 2, None),
+            visit("https://a.com/x/", 9, Some(10)),
+        ];
+        let aggregated = aggregate_visits(visits, None);
+        let summary: Vec<(&str, usize, u32, u32, Option<i64>)> = aggregated
+            .iter()
+            .map(|a| {
+                (
+                    a.url.as_str(),
+                    a.count,
+                    a.first.minute(),
+                    a.last.minute(),
+                    a.dwell.map(|d| d.num_seconds()),
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                ("https://a.com/x", 3, 2, 9, Some(40)),
+                ("https://b.com", 1, 1, 1, None)
+            ]
+        );
+    }
+
+    #[test]
+    fn aggregate_visits_groups_by_rollup_depth() {
+        let visits = vec![
+            visit("https://github.com/owner/repo/issues/1", 0, None),
+            visit("https://github.com/owner/repo/pulls", 1, None),
+            visit("https://github.com/other/repo", 2, None),
+        ];
+        let urls: Vec<(String, usize)> = aggregate_visits(visits, Some(1))
+            .into_iter()
+            .map(|a| (a.url, a.count))
+            .collect();
+        assert_eq!(
+            urls,
+            [
+                ("https://github.com/owner/*".to_string(), 2),
+                ("https://github.com/other/*".to_string(), 1)
+            ]
+        );
+    }
+}
@@ -0,0 +1,441 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+use crate::{squid_status, LogEntry};
+
+// ------------ offline capture formats ------------------------------------
+/// Turns a CONNECT target or relative request line into an absolute URL.
+fn absolute_url(method: &str, target: &str, base_url: Option<&str>) -> Option<String> {
+    if target.starts_with("http://") || target.starts_with("https://") {
+        Some(target.to_string())
+    } else if method == "CONNECT" {
+        let host = target.strip_suffix(":443").unwrap_or(target);
+        Some(format!("https://{host}"))
+    } else if target.starts_with('/') {
+        base_url.map(|base| format!("{}{target}", base.trim_end_matches('/')))
+    } else {
+        None
+    }
+}
+
+fn dash_to_none(value: &str) -> Option<String> {
+    (value != "-" && !value.is_empty()).then(|| value.to_string())
+}
+
+// ------------ HAR --------------------------------------------------------
+/// Parses a HAR 1.2 export from the browser's developer tools (`log.entries[]`).
+pub fn parse_har(text: &str) -> Result<Vec<LogEntry>> {
+    let har: Value = serde_json::from_str(text).context("Invalid HAR file")?;
+    let entries = har
+        .pointer("/log/entries")
+        .and_then(Value::as_array)
+        .ok_or_else(|| anyhow::anyhow!("HAR file has no log.entries"))?;
+
+    Ok(entries
+        .iter()
+        .filter_map(|entry| {
+            let request = entry.get("request")?;
+            let url = request.get("url")?.as_str()?;
+            if !(url.starts_with("http://") || url.starts_with("https://")) {
+                return None;
+            }
+            let ts = entry
+                .get("startedDateTime")
+                .and_then(Value::as_str)
+                .and_then(|t| DateTime::parse_from_rfc3339(t).ok())?
+                .with_timezone(&Utc);
+            let referer = request
+                .get("headers")
+                .and_then(Value::as_array)
+                .into_iter()
+                .flatten()
+                .find(|h| {
+                    h.get("name")
+                        .and_then(Value::as_str)
+                        .is_some_and(|n| n.eq_ignore_ascii_case("referer"))
+                })
+                .and_then(|h| h.get("value")?.as_str())
+                .map(str::to_string);
+            let status = entry
+                .pointer("/response/status")
+                .and_then(Value::as_u64)
+                .and_then(|s| u16::try_from(s).ok())
+                .filter(|s| *s != 0);
+            // -1 when the size is unknown
+            let bytes = entry.pointer("/response/bodySize").and_then(Value::as_u64);
+            Some(LogEntry {
+                referer,
+                status,
+                bytes,
+                ..LogEntry::new(url.to_string(), ts)
+            })
+        })
+        .collect())
+}
+
+// ------------ mitmproxy flows --------------------------------------------
+/// A decoded tnetstring value, the serialization mitmproxy uses for flow files.
+enum TNet {
+    Bytes(Vec<u8>),
+    Int(i64),
+    Float(f64),
+    /// Booleans and null, which flows only use for fields we ignore
+    Other,
+    List(Vec<TNet>),
+    Dict(BTreeMap<String, TNet>),
+}
+
+impl TNet {
+    fn get(&self, key: &str) -> Option<&Self> {
+        match self {
+            Self::Dict(map) => map.get(key),
+            _ => None,
+        }
+    }
+
+    fn as_str(&self) -> Option<String> {
+        match self {
+            Self::Bytes(b) => Some(String::from_utf8_lossy(b).into_owned()),
+            _ => None,
+        }
+    }
+
+    const fn as_int(&self) -> Option<i64> {
+        match self {
+            Self::Int(i) => Some(*i),
+            _ => None,
+        }
+    }
+
+    const fn as_f64(&self) -> Option<f64> {
+        match self {
+            Self::Float(f) => Some(*f),
+            Self::Int(i) => Some(*i as f64),
+            _ => None,
+        }
+    }
+}
+
+/// Deeper nesting than mitmproxy ever writes; bounds the recursion on bad input.
+const MAX_TNET_DEPTH: usize = 64;
+
+/// Parses one tnetstring (`<len>:<payload><tag>`), returning it and the rest.
+fn parse_tnetstring(data: &[u8]) -> Result<(TNet, &[u8])> {
+    parse_tnet_at(data, 0)
+}
+
+fn parse_tnet_at(data: &[u8], depth: usize) -> Result<(TNet, &[u8])> {
+    if depth > MAX_TNET_DEPTH {
+        anyhow::bail!("tnetstring nested deeper than {MAX_TNET_DEPTH} levels");
+    }
+    let colon = data
+        .iter()
+        .position(|&b| b == b':')
+        .ok_or_else(|| anyhow::anyhow!("truncated tnetstring"))?;
+    let len: usize = std::str::from_utf8(&data[..colon])?.parse()?;
+    let end = (colon + 1)
+        .checked_add(len)
+        .ok_or_else(|| anyhow::anyhow!("tnetstring length {len} is out of range"))?;
+    let payload = data
+        .get(colon + 1..end)
+        .ok_or_else(|| anyhow::anyhow!("truncated tnetstring payload"))?;
+    let tag = *data
+        .get(end)
+        .ok_or_else(|| anyhow::anyhow!("missing tnetstring type tag"))?;
+    let rest = &data[end + 1..];
+    let text = || String::from_utf8_lossy(payload);
+
+    let value = match tag {
+        b',' | b';' => TNet::Bytes(payload.to_vec()),
+        b'#' => TNet::Int(text().parse()?),
+        b'^' => TNet::Float(text().parse()?),
+        b'!' | b'~' => TNet::Other,
+        b']' => {
+            let mut items = Vec::new();
+            let mut remaining = payload;
+            while !remaining.is_empty() {
+                let (item, next) = parse_tnet_at(remaining, depth + 1)?;
+                items.push(item);
+                remaining = next;
+            }
+            TNet::List(items)
+        }
+        b'}' => {
+            let mut map = BTreeMap::new();
+            let mut remaining = payload;
+            while !remaining.is_empty() {
+                let (key, next) = parse_tnet_at(remaining, depth + 1)?;
+                let (value, next) = parse_tnet_at(next, depth + 1)?;
+                map.insert(key.as_str().unwrap_or_default(), value);
+                remaining = next;
+            }
+            TNet::Dict(map)
+        }
+        other => anyhow::bail!("unknown tnetstring type tag {:?}", other as char),
+    };
+    Ok((value, rest))
+}
+
+fn flow_to_entry(flow: &TNet) -> Option<LogEntry> {
+    let request = flow.get("request")?;
+    let method = request.get("method")?.as_str()?;
+    let scheme = request.get("scheme")?.as_str()?;
+    let host = request.get("host")?.as_str()?;
+    let path = request
+        .get("path")
+        .and_then(TNet::as_str)
+        .unwrap_or_default();
+    let port = request.get("port").and_then(TNet::as_f64).map(|p| p as u16);
+    let default_port = if scheme == "https" { 443 } else { 80 };
+    let authority = match port {
+        Some(p) if p != default_port => format!("{host}:{p}"),
+        _ => host,
+    };
+    let url = if method == "CONNECT" {
+        format!("https://{authority}")
+    } else {
+        format!("{scheme}://{authority}{path}")
+    };
+
+    let started = request.get("timestamp_start")?.as_f64()?;
+    let ts = DateTime::from_timestamp_micros((started * 1_000_000.0) as i64)?;
+
+    let header = |name: &str| match request.get("headers")? {
+        TNet::List(headers) => headers.iter().find_map(|h| match h {
+            TNet::List(pair) if pair.len() == 2 => pair[0]
+                .as_str()
+                .filter(|n| n.eq_ignore_ascii_case(name))
+                .and_then(|_| pair[1].as_str()),
+            _ => None,
+        }),
+        _ => None,
+    };
+    // The client address moved from `address` to `peername` in mitmproxy 7
+    let client = flow.get("client_conn").and_then(|c| {
+        match c.get("peername").or_else(|| c.get("address"))? {
+            TNet::List(addr) => addr.first()?.as_str(),
+            TNet::Dict(_) => c.get("address")?.get("address")?.as_str(),
+            _ => None,
+        }
+    });
+
+    let response = flow.get("response");
+    let status = response
+        .and_then(|r| r.get("status_code"))
+        .and_then(TNet::as_int)
+        .and_then(|s| u16::try_from(s).ok())
+        .filter(|s| *s != 0);
+    let bytes = match response.and_then(|r| r.get("content")) {
+        Some(TNet::Bytes(content)) => Some(content.len() as u64),
+        _ => None,
+    };
+
+    Some(LogEntry {
+        client,
+        referer: header("referer"),
+        status,
+        bytes,
+        ..LogEntry::new(url, ts)
+    })
+}
+
+/// Parses a mitmproxy flow dump (`mitmdump -w`), a concatenation of tnetstrings.
+pub fn parse_mitmproxy_flows(data: &[u8]) -> Result<Vec<LogEntry>> {
+    let mut entries = Vec::new();
+    let mut remaining = data;
+    while !remaining.iter().all(u8::is_ascii_whitespace) {
+        let (flow, next) = parse_tnetstring(remaining).context("Invalid mitmproxy flow file")?;
+        let is_http = flow
+            .get("type")
+            .and_then(TNet::as_str)
+            .is_none_or(|t| t == "http");
+        if is_http {
+            entries.extend(flow_to_entry(&flow));
+        }
+        remaining = next;
+    }
+    Ok(entries)
+}
+
+// ------------ text access logs -------------------------------------------
+/// Parses Squid's default `squid` log format:
+/// `time elapsed client code/status bytes method URL user hierarchy/peer type`
+pub fn parse_squid_native(text: &str) -> Vec<LogEntry> {
+    text.lines()
+        .filter_map(|line| {
+            let parts: Vec<&str> = line.split_whitespace().collect();
+            if parts.len() < 7 {
+                return None;
+            }
+            let secs: f64 = parts[0].parse().ok()?;
+            let ts = DateTime::from_timestamp_micros((secs * 1_000_000.0) as i64)?;
+            let url = absolute_url(parts[5], parts[6], None)?;
+            Some(LogEntry {
+                client: dash_to_none(parts[2]),
+                user: parts.get(7).and_then(|u| dash_to_none(u)),
+                status: squid_status(parts[3]),
+                bytes: parts[4].parse().ok(),
+                ..LogEntry::new(url, ts)
+            })
+        })
+        .collect()
+}
+
+/// Splits a combined-log line into fields, keeping `[...]` and `"..."` groups whole.
+fn split_log_fields(line: &str) -> Vec<&str> {
+    let mut fields = Vec::new();
+    let mut rest = line.trim();
+    while !rest.is_empty() {
+        let (field, next) = match rest.chars().next() {
+            Some('"') => rest[1..]
+                .find('"')
+                .map_or((&rest[1..], ""), |end| (&rest[1..=end], &rest[end + 2..])),
+            Some('[') => rest[1..]
+                .find(']')
+                .map_or((&rest[1..], ""), |end| (&rest[1..=end], &rest[end + 2..])),
+            _ => rest
+                .find(' ')
+                .map_or((rest, ""), |end| (&rest[..end], &rest[end..])),
+        };
+        fields.push(field);
+        rest = next.trim_start();
+    }
+    fields
+}
+
+/// Parses Apache/NGINX combined log format:
+/// `client ident user [time] "METHOD target PROTO" status bytes "referer" "agent"`.
+/// Relative targets (server logs) are resolved against `base_url`.
+pub fn parse_combined_log(text: &str, base_url: Option<&str>) -> Vec<LogEntry> {
+    text.lines()
+        .filter_map(|line| {
+            let fields = split_log_fields(line);
+            let request = fields.get(4)?;
+            let mut request_parts = request.split_whitespace();
+            let (method, target) = (request_parts.next()?, request_parts.next()?);
+            let ts = DateTime::parse_from_str(fields.get(3)?, "%d/%b/%Y:%H:%M:%S %z")
+                .ok()?
+                .with_timezone(&Utc);
+            let url = absolute_url(method, target, base_url)?;
+            Some(LogEntry {
+                client: dash_to_none(fields[0]),
+                user: dash_to_none(fields.get(2)?),
+                referer: fields.get(7).and_then(|r| dash_to_none(r)),
+                status: fields.get(5).and_then(|s| s.parse().ok()),
+                bytes: fields.get(6).and_then(|s| s.parse().ok()),
+                ..LogEntry::new(url, ts)
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tnet(payload: &str, tag: char) -> String {
+        format!("{}:{payload}{tag}", payload.len())
+    }
+
+    #[test]
+    fn parses_nested_tnetstrings() -> Result<()> {
+        let dict = [
+            tnet("method", ','),
+            tnet("GET", ','),
+            tnet("status", ','),
+            tnet("200", '#'),
+            tnet("items", ','),
+            tnet(&(tnet("a", ',') + &tnet("1.5", '^')), ']'),
+        ]
+        .concat();
+        let data = tnet(&dict, '}') + "rest";
+        let (value, rest) = parse_tnetstring(data.as_bytes())?;
+        assert_eq!(rest, b"rest");
+        assert_eq!(
+            value.get("method").and_then(TNet::as_str).as_deref(),
+            Some("GET")
+        );
+        assert_eq!(value.get("status").and_then(TNet::as_int), Some(200));
+        let Some(TNet::List(items)) = value.get("items") else {
+            anyhow::bail!("items isn't a list");
+        };
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[1].as_f64(), Some(1.5));
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_malformed_tnetstrings() {
+        for input in [
+            "",
+            "5",
+            "x:abc,",
+            "5:abc,",
+            "3:abc",
+            "3:abc?",
+            "3:abc#",
+            "18446744073709551615:x,",
+            "18446744073709551614:x,",
+            "99999999999999999999999:x,",
+            "4:3:ab]",
+        ] {
+            assert!(
+                parse_tnetstring(input.as_bytes()).is_err(),
+                "{input:?} was accepted"
+            );
+        }
+    }
+
+    #[test]
+    fn caps_tnetstring_nesting() {
+        let nest = |depth: usize| (0..depth).fold(tnet("", ']'), |inner, _| tnet(&inner, ']'));
+        assert!(parse_tnetstring(nest(MAX_TNET_DEPTH).as_bytes()).is_ok());
+        let err = parse_tnetstring(nest(MAX_TNET_DEPTH + 1).as_bytes()).err();
+        assert!(err.is_some_and(|e| e.to_string().contains("nested deeper")));
+    }
+
+    #[test]
+    fn parses_combined_log_lines() {
+        let text = concat!(
+            r#"10.0.0.5 - alice [18/Oct/2026:14:38:48 +0200] "GET /docs?q=1 HTTP/1.1" 200 5120 "https://ref.example/" "Mozilla/5.0""#,
+            "\n",
+            r#"10.0.0.6 - - [18/Oct/2026:14:39:00 +0000] "CONNECT api.example:443 HTTP/1.1" 200 - "-" "curl""#,
+        );
+        let entries = parse_combined_log(text, Some("https://site.example/"));
+        assert_eq!(entries.len(), 2);
+        let first = &entries[0];
+        assert_eq!(first.url, "https://site.example/docs?q=1");
+        assert_eq!(first.ts.to_rfc3339(), "2026-10-18T12:38:48+00:00");
+        assert_eq!(first.client.as_deref(), Some("10.0.0.5"));
+        assert_eq!(first.user.as_deref(), Some("alice"));
+        assert_eq!(first.referer.as_deref(), Some("https://ref.example/"));
+        assert_eq!((first.status, first.bytes), (Some(200), Some(5120)));
+        let second = &entries[1];
+        assert_eq!(second.url, "https://api.example");
+        assert_eq!(
+            (second.user.as_deref(), second.referer.as_deref()),
+            (None, None)
+        );
+        assert_eq!(second.bytes, None);
+    }
+
+    #[test]
+    fn skips_malformed_combined_log_lines() {
+        let text = concat!(
+            "\n",
+            "garbage\n",
+            r#"10.0.0.5 - - [not a date] "GET /x HTTP/1.1" 200 1"#,
+            "\n",
+            r#"10.0.0.5 - - [18/Oct/2026:14:38:48 +0000] "GET""#,
+            "\n",
+            r#"10.0.0.5 - - [18/Oct/2026:14:38:48 +0000] "GET /relative HTTP/1.1" 200 1"#,
+            "\n",
+            r#"10.0.0.5 - - [18/Oct/2026:14:38:48 +0000] "GET /unterminated"#,
+            "\n",
+            "é [ \"",
+        );
+        assert!(parse_combined_log(text, None).is_empty());
+    }
+}
@@ -0,0 +1,258 @@
+use anyhow::{Context, Result};
+use std::{collections::BTreeMap, fmt::Write as _, fs};
+
+use crate::{
+    config_dir,
+    rules::{host_of, in_domain},
+    session::is_page_view,
+    LogEntry,
+};
+
+// ------------ categories ---------------------------------------------------
+const CATEGORIES_FILE: &str = "categories.conf";
+/// Category for pages no rule matches.
+const UNCATEGORIZED: &str = "other";
+/// Domains listed per category in the prompt.
+const PROMPT_DOMAINS: usize = 3;
+
+/// Built-in taxonomy, in the same format as `categories.conf`.
+const BUILTIN: &str = "
+docs docs.rs doc.rust-lang.org developer.mozilla.org docs.python.org readthedocs.io pkg.go.dev
+docs learn.microsoft.com devdocs.io cppreference.com man7.org docs.github.com wikipedia.org
+code github.com gitlab.com bitbucket.org codeberg.org sr.ht gist.github.com crates.io npmjs.com
+code pypi.org stackoverflow.com stackexchange.com
+work slack.com teams.microsoft.com zoom.us meet.google.com notion.so atlassian.net linear.app
+work figma.com docs.google.com drive.google.com calendar.google.com mail.google.com
+work outlook.office.com outlook.live.com
+news news.ycombinator.com lobste.rs bbc.com bbc.co.uk nytimes.com theguardian.com reuters.com
+news apnews.com arstechnica.com theverge.com lwn.net
+social twitter.com x.com facebook.com instagram.com linkedin.com reddit.com mastodon.social
+social bsky.app threads.net tiktok.com
+shopping amazon.com amazon.co.uk amazon.de ebay.com etsy.com aliexpress.com bestbuy.com walmart.com
+video youtube.com youtu.be vimeo.com twitch.tv netflix.com
+";
+
+struct Rule {
+    category: String,
+    domain: String,
+    /// Path prefix, empty to match the whole domain
+    path: String,
+}
+
+impl Rule {
+    fn matches(&self, host: &str, path: &str) -> bool {
+        let path_matches = path
+            .strip_prefix(self.path.as_str())
+            .is_some_and(|rest| self.path.is_empty() || rest.is_empty() || rest.starts_with('/'));
+        in_domain(host, &self.domain) && path_matches
+    }
+}
+
+/// Maps hosts and paths to topic categories so the summarizer gets
+/// deterministic counts instead of guessing them from URLs.
+pub struct Taxonomy {
+    user: Vec<Rule>,
+    builtin: Vec<Rule>,
+}
+
+impl Taxonomy {
+    /// Loads `categories.conf` from the config dir on top of the built-in
+    /// taxonomy. Each line is `<category> <domain>[/<path>]...`; user rules
+    /// win over built-in ones, and a missing file means built-ins only.
+    pub fn load() -> Result<Self> {
+        let builtin = parse(BUILTIN)?;
+        let path = config_dir()?.join(CATEGORIES_FILE);
+        let user = match fs::read_to_string(&path) {
+            Ok(contents) => parse(&contents)
+                .with_context(|| format!("Invalid category in {}", path.display()))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        };
+        Ok(Self { user, builtin })
+    }
+
+    /// Built-in rules only, for when `categories.conf` can't be read.
+    pub fn builtin() -> Self {
+        Self {
+            user: Vec::new(),
+            builtin: parse(BUILTIN).unwrap_or_default(),
+        }
+    }
+
+    /// The category of `url`; the most specific matching rule wins.
+    pub fn categorize(&self, url: &str) -> Option<&str> {
+        let host = host_of(url)?;
+        let path = url::Url::parse(url).map_or_else(|_| "/".to_string(), |u| u.path().to_string());
+        best_match(&self.user, &host, &path).or_else(|| best_match(&self.builtin, &host, &path))
+    }
+
+    /// Page views per category with their top domains, for the system prompt.
+    /// Current rules win over categories stored with the entries, so edits to
+    /// `categories.conf` apply to past traffic too.
+    pub fn render_counts(&self, entries: &[LogEntry]) -> String {
+        let mut categories: BTreeMap<&str, BTreeMap<String, usize>> = BTreeMap::new();
+        let mut total = 0usize;
+        for entry in entries
+            .iter()
+            .filter(|e| !e.is_marker() && is_page_view(&e.url))
+        {
+            let category = self
+                .categorize(&entry.url)
+                .or(entry.category.as_deref())
+                .unwrap_or(UNCATEGORIZED);
+            let host = host_of(&entry.url).unwrap_or_default();
+            *categories
+                .entry(category)
+                .or_default()
+                .entry(host)
+                .or_default() += 1;
+            total += 1;
+        }
+
+        let mut counts: Vec<_> = categories
+            .into_iter()
+            .map(|(category, domains)| {
+                let mut domains: Vec<(String, usize)> = domains.into_iter().collect();
+                domains.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+                (
+                    category,
+                    domains.iter().map(|(_, n)| n).sum::<usize>(),
+                    domains,
+                )
+            })
+            .collect();
+        counts.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+
+        let mut out = String::new();
+        for (category, count, domains) in counts {
+            let top: Vec<&str> = domains
+                .iter()
+                .take(PROMPT_DOMAINS)
+                .map(|(d, _)| d.as_str())
+                .collect();
+            let _ = writeln!(
+                out,
+                "- {category}: {count} page views ({:.0}%): {}",
+                count as f64 * 100.0 / total as f64,
+                top.join(", ")
+            );
+        }
+        out
+    }
+
+    /// Stores each entry's category with it, leaving unmatched entries alone.
+    pub fn tag(&self, entries: &mut [LogEntry]) {
+        for entry in entries.iter_mut().filter(|e| !e.is_marker()) {
+            if let Some(category) = self.categorize(&entry.url) {
+                entry.category = Some(category.to_string());
+            }
+        }
+    }
+}
+
+/// The longest domain and path prefix matching wins; ties go to the earlier rule.
+fn best_match<'a>(rules: &'a [Rule], host: &str, path: &str) -> Option<&'a str> {
+    rules
+        .iter()
+        .rev()
+        .filter(|r| r.matches(host, path))
+        .max_by_key(|r| (r.domain.len(), r.path.len()))
+        .map(|r| r.category.as_str())
+}
+
+fn parse(contents: &str) -> Result<Vec<Rule>> {
+    let mut rules = Vec::new();
+    for (n, line) in contents.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("").trim();
+        let mut parts = line.split_whitespace();
+        let Some(category) = parts.next() else {
+            continue;
+        };
+        if !category
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            anyhow::bail!(
+                "line {}: category names may only contain letters, digits, '-' and '_'",
+                n + 1
+            );
+        }
+        let before = rules.len();
+        for pattern in parts {
+            let (domain, path) = pattern
+                .find('/')
+                .map_or((pattern, ""), |i| pattern.split_at(i));
+            rules.push(Rule {
+                category: category.to_ascii_lowercase(),
+                domain: domain
+                    .trim_start_matches("*.")
+                    .trim_end_matches('.')
+                    .to_ascii_lowercase(),
+                path: path.trim_end_matches('/').to_string(),
+            });
+        }
+        if rules.len() == before {
+            anyhow::bail!("line {}: category `{category}` has no domains", n + 1);
+        }
+    }
+    Ok(rules)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_normalizes_patterns_and_skips_comments() {
+        let rules = parse("# comment\n\nDocs *.Example.COM./guide/ rust-lang.org # trailing\n")
+            .unwrap_or_default();
+        let parsed: Vec<(&str, &str, &str)> = rules
+            .iter()
+            .map(|r| (r.category.as_str(), r.domain.as_str(), r.path.as_str()))
+            .collect();
+        assert_eq!(
+            parsed,
+            [
+                ("docs", "example.com", "/guide"),
+                ("docs", "rust-lang.org", "")
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_rejects_malformed_lines() {
+        let err = parse("work example.com\nbad/name example.org\n").err();
+        assert!(err.is_some_and(|e| e.to_string().starts_with("line 2:")));
+        let err = parse("lonely\n").err();
+        assert!(err.is_some_and(|e| e.to_string().contains("has no domains")));
+        assert!(parse("").is_ok_and(|rules| rules.is_empty()));
+    }
+
+    #[test]
+    fn best_match_prefers_the_most_specific_rule() {
+        let rules = parse("code github.com\nwork github.com/mycorp\nnews blog.github.com\n")
+            .unwrap_or_default();
+        assert_eq!(
+            best_match(&rules, "github.com", "/rust-lang/rust"),
+            Some("code")
+        );
+        assert_eq!(
+            best_match(&rules, "github.com", "/mycorp/app"),
+            Some("work")
+        );
+        assert_eq!(best_match(&rules, "github.com", "/mycorp"), Some("work"));
+        // Path prefixes only match whole segments
+        assert_eq!(
+            best_match(&rules, "github.com", "/mycorporate"),
+            Some("code")
+        );
+        assert_eq!(best_match(&rules, "blog.github.com", "/post"), Some("news"));
+        assert_eq!(best_match(&rules, "notgithub.com", "/"), None);
+    }
+
+    #[test]
+    fn best_match_ties_go_to_the_earlier_rule() {
+        let rules = parse("first example.com\nsecond example.com\n").unwrap_or_default();
+        assert_eq!(best_match(&rules, "www.example.com", "/"), Some("first"));
+    }
+}
@@ -0,0 +1,527 @@
+use anyhow::{Context, Result};
+use async_openai::{config::OpenAIConfig, Client};
+use clap::Args;
+use std::{
+    fmt, fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+use toml::{Table, Value};
+
+use crate::{config_dir, memory, secrets::ApiKey, storage::restrict_permissions};
+
+// ------------ layered configuration ----------------------------------------
+const CONFIG_FILE: &str = "config.toml";
+
+const CONFIG_TEMPLATE: &str = r#"# Configuration for digital-twin-proxy
+#
+# Environment variables (including `.env`) override these values, and
+# command-line flags override both. `digital-twin-proxy config show` prints
+# the effective settings and where each one came from.
+
+# Profile to use when neither --profile nor CONFIG_PROFILE is given
+# profile = "openai"
+
+[llm]
+# api_base = "http://localhost:11434/v1"
+# api_key = ""
+# api_key_file = "~/.config/ai-proxy/api-key"   # or read it from a file
+# api_key_cmd = "pass show openai"              # or from a command's output
+# model = "gpt-oss:20b"
+# embedding_model = "hash"   # for `recall`, or an /embeddings model like "nomic-embed-text"
+# max_items = 500     # safety cap on URLs per `analyze`
+# interval = 30       # seconds between `ambient` summaries
+
+[proxy]
+# pac_port = 8889     # also serve a PAC file on this port
+
+[privacy]
+# tls_intercept = false
+# rollup_depth = 2    # group URLs by their first N path segments
+
+[storage]
+# keep_raw_days = 30
+# encryption_key_file = "/path/to/key"
+
+# Profiles override any of the sections above
+# [profiles.openai.llm]
+# api_base = "https://api.openai.com/v1"
+# model = "gpt-5"
+"#;
+
+/// A setting that can come from a flag, the environment, the selected
+/// profile, `config.toml` or a built-in default, in that order.
+pub struct Key {
+    section: &'static str,
+    name: &'static str,
+    env: &'static str,
+    default: Option<&'static str>,
+    secret: bool,
+}
+
+pub const API_BASE: Key = Key {
+    section: "llm",
+    name: "api_base",
+    env: "API_BASE",
+    default: None,
+    secret: false,
+};
+pub const API_KEY: Key = Key {
+    section: "llm",
+    name: "api_key",
+    env: "API_KEY",
+    default: None,
+    secret: true,
+};
+pub const API_KEY_FILE: Key = Key {
+    section: "llm",
+    name: "api_key_file",
+    env: "API_KEY_FILE",
+    default: None,
+    secret: false,
+};
+pub const API_KEY_CMD: Key = Key {
+    section: "llm",
+    name: "api_key_cmd",
+    env: "API_KEY_CMD",
+    default: None,
+    secret: false,
+};
+pub const MODEL: Key = Key {
+    section: "llm",
+    name: "model",
+    env: "MODEL",
+    default: Some(crate::DEFAULT_MODEL),
+    secret: false,
+};
+pub const EMBEDDING_MODEL: Key = Key {
+    section: "llm",
+    name: "embedding_model",
+    env: "EMBEDDING_MODEL",
+    default: Some(crate::DEFAULT_EMBEDDING_MODEL),
+    secret: false,
+};
+pub const MAX_ITEMS: Key = Key {
+    section: "llm",
+    name: "max_items",
+    env: "MAX_ANALYSIS_ITEMS",
+    default: Some("500"),
+    secret: false,
+};
+pub const INTERVAL: Key = Key {
+    section: "llm",
+    name: "interval",
+    env: "AMBIENT_INTERVAL",
+    default: Some("30"),
+    secret: false,
+};
+pub const PAC_PORT: Key = Key {
+    section: "proxy",
+    name: "pac_port",
+    env: "PAC_PORT",
+    default: None,
+    secret: false,
+};
+pub const TLS_INTERCEPT: Key = Key {
+    section: "privacy",
+    name: "tls_intercept",
+    env: "TLS_INTERCEPT",
+    default: Some("false"),
+    secret: false,
+};
+pub const ROLLUP_DEPTH: Key = Key {
+    section: "privacy",
+    name: "rollup_depth",
+    env: "ROLLUP_DEPTH",
+    default: None,
+    secret: false,
+};
+pub const KEEP_RAW_DAYS: Key = Key {
+    section: "storage",
+    name: "keep_raw_days",
+    env: "RETAIN_RAW_DAYS",
+    default: Some("30"),
+    secret: false,
+};
+pub const ENCRYPTION_KEY_FILE: Key = Key {
+    section: "storage",
+    name: "encryption_key_file",
+    env: "ENCRYPTION_KEY_FILE",
+    default: None,
+    secret: false,
+};
+
+const KEYS: &[&Key] = &[
+    &API_BASE,
+    &API_KEY,
+    &API_KEY_FILE,
+    &API_KEY_CMD,
+    &MODEL,
+    &EMBEDDING_MODEL,
+    &MAX_ITEMS,
+    &INTERVAL,
+    &PAC_PORT,
+    &TLS_INTERCEPT,
+    &ROLLUP_DEPTH,
+    &KEEP_RAW_DAYS,
+    &ENCRYPTION_KEY_FILE,
+];
+
+/// Where an effective value came from.
+enum Source {
+    Env(&'static str),
+    Profile(String),
+    File,
+    Default,
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Env(name) => write!(f, "env {name}"),
+            Self::Profile(name) => write!(f, "profile {name}"),
+            Self::File => f.write_str(CONFIG_FILE),
+            Self::Default => f.write_str("default"),
+        }
+    }
+}
+
+/// LLM endpoint flags shared by `analyze`, `ambient` and `doctor`.
+#[derive(Args)]
+pub struct LlmArgs {
+    /// Model to use (env: `MODEL`, config: `llm.model`)
+    #[arg(short, long)]
+    model: Option<String>,
+    /// OpenAI-compatible API base URL (env: `API_BASE`, config: `llm.api_base`)
+    #[arg(long)]
+    api_base: Option<String>,
+    /// API key for the endpoint; visible in `ps`, so prefer the options below
+    /// (env: `API_KEY`, config: `llm.api_key`)
+    #[arg(long)]
+    api_key: Option<String>,
+    /// Read the API key from this file (env: `API_KEY_FILE`, config: `llm.api_key_file`)
+    #[arg(long, conflicts_with = "api_key")]
+    api_key_file: Option<PathBuf>,
+    /// Run this command and use the first line it prints as the API key,
+    /// e.g. "pass show openai" (env: `API_KEY_CMD`, config: `llm.api_key_cmd`)
+    #[arg(long, conflicts_with_all = ["api_key", "api_key_file"])]
+    api_key_cmd: Option<String>,
+    /// Model for page embeddings, or "hash" for built-in hash embeddings
+    /// (env: `EMBEDDING_MODEL`, config: `llm.embedding_model`)
+    #[arg(long)]
+    embedding_model: Option<String>,
+}
+
+/// The resolved LLM endpoint.
+#[derive(Clone)]
+pub struct Llm {
+    pub model: String,
+    pub embedding_model: String,
+    pub api_base: String,
+    pub api_key: ApiKey,
+}
+
+impl Llm {
+    /// A client for the endpoint, with the API key resolved now.
+    pub async fn client(&self) -> Result<Client<OpenAIConfig>> {
+        let mut config = OpenAIConfig::new().with_api_base(&self.api_base);
+        if let Some(key) = self.api_key.resolve_async().await? {
+            config = config.with_api_key(key);
+        }
+        Ok(Client::with_config(config))
+    }
+
+    /// A copy with the API key looked up once, so a key command isn't run
+    /// again for every request and tool call of one summary.
+    pub async fn with_resolved_key(&self) -> Result<Self> {
+        Ok(Self {
+            api_key: ApiKey::Resolved(self.api_key.resolve_async().await?),
+            ..self.clone()
+        })
+    }
+}
+
+/// `config.toml` plus the selected profile, ready to be merged with env and flags.
+pub struct Settings {
+    path: PathBuf,
+    base: Table,
+    profile: Option<(String, Table)>,
+}
+
+fn config_path() -> Result<PathBuf> {
+    Ok(config_dir()?.join(CONFIG_FILE))
+}
+
+/// Rejects unknown sections and keys so typos don't silently fall back to defaults.
+fn check_sections(table: &Table, path: &Path, prefix: &str) -> Result<()> {
+    for (section, values) in table {
+        let Value::Table(values) = values else {
+            anyhow::bail!(
+                "`{prefix}{section}` in {} must be a [section]",
+                path.display()
+            );
+        };
+        for name in values.keys() {
+            if !KEYS.iter().any(|k| k.section == section && k.name == name) {
+                anyhow::bail!(
+                    "Unknown setting `{prefix}{section}.{name}` in {}",
+                    path.display()
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+fn lookup(table: &Table, key: &Key) -> Option<String> {
+    match table.get(key.section)?.get(key.name)? {
+        Value::String(s) => Some(s.clone()),
+        value => Some(value.to_string()),
+    }
+}
+
+impl Settings {
+    /// Reads `config.toml` (if any) and selects `profile`, or the file's own
+    /// `profile = "..."` when none is given.
+    pub fn load(profile: Option<&str>) -> Result<Self> {
+        let path = config_path()?;
+        let mut base: Table = match fs::read_to_string(&path) {
+            Ok(text) => text
+                .parse()
+                .with_context(|| format!("Failed to parse {}", path.display()))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Table::new(),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        };
+
+        let default_profile = match base.remove("profile") {
+            None => None,
+            Some(Value::String(name)) => Some(name),
+            Some(_) => anyhow::bail!("`profile` in {} must be a string", path.display()),
+        };
+        let mut profiles = match base.remove("profiles") {
+            None => Table::new(),
+            Some(Value::Table(profiles)) => profiles,
+            Some(_) => anyhow::bail!("`profiles` in {} must be a table", path.display()),
+        };
+        check_sections(&base, &path, "")?;
+        for (name, profile) in &profiles {
+            let Value::Table(profile) = profile else {
+                anyhow::bail!("`profiles.{name}` in {} must be a table", path.display());
+            };
+            check_sections(profile, &path, &format!("profiles.{name}."))?;
+        }
+
+        let profile = match profile.map(str::to_string).or(default_profile) {
+            None => None,
+            Some(name) => match profiles.remove(&name) {
+                Some(Value::Table(table)) => Some((name, table)),
+                _ => anyhow::bail!("Profile `{name}` not found in {}", path.display()),
+            },
+        };
+        Ok(Self {
+            path,
+            base,
+            profile,
+        })
+    }
+
+    /// Environment and defaults only, for `config show` when the file is broken.
+    pub fn without_file() -> Result<Self> {
+        Ok(Self {
+            path: config_path()?,
+            base: Table::new(),
+            profile: None,
+        })
+    }
+
+    /// `key`'s value in the environment, the profile and the file, in that order.
+    fn layers(&self, key: &Key) -> [Option<(String, Source)>; 3] {
+        [
+            std::env::var(key.env)
+                .ok()
+                .filter(|v| !v.is_empty())
+                .map(|value| (value, Source::Env(key.env))),
+            self.profile.as_ref().and_then(|(name, table)| {
+                lookup(table, key).map(|value| (value, Source::Profile(name.clone())))
+            }),
+            lookup(&self.base, key).map(|value| (value, Source::File)),
+        ]
+    }
+
+    /// The effective raw value of `key`, ignoring flags, and where it came from.
+    fn raw(&self, key: &Key) -> Option<(String, Source)> {
+        self.layers(key)
+            .into_iter()
+            .flatten()
+            .next()
+            .or_else(|| key.default.map(|d| (d.to_string(), Source::Default)))
+    }
+
+    /// `flag` if given, otherwise the first layer that sets `key`.
+    pub fn get<T>(&self, key: &Key, flag: Option<T>) -> Result<Option<T>>
+    where
+        T: FromStr,
+        T::Err: fmt::Display,
+    {
+        if flag.is_some() {
+            return Ok(flag);
+        }
+        self.raw(key)
+            .map(|(raw, source)| {
+                raw.parse().map_err(|e| {
+                    anyhow::anyhow!(
+                        "Invalid {}.{} {raw:?} (from {source}): {e}",
+                        key.section,
+                        key.name
+                    )
+                })
+            })
+            .transpose()
+    }
+
+    /// Like `get` for settings with a built-in default.
+    pub fn value<T>(&self, key: &Key, flag: Option<T>) -> Result<T>
+    where
+        T: FromStr,
+        T::Err: fmt::Display,
+    {
+        self.get(key, flag)?
+            .with_context(|| format!("No value for {}.{}", key.section, key.name))
+    }
+
+    /// A boolean switch: the flag (`--x` or `--no-x`) if given, otherwise
+    /// from env or config. Anything but true/false, yes/no, on/off or 1/0 is
+    /// an error rather than a guess.
+    pub fn switch(&self, key: &Key, flag: Option<bool>) -> Result<bool> {
+        if let Some(flag) = flag {
+            return Ok(flag);
+        }
+        let Some((raw, source)) = self.raw(key) else {
+            return Ok(false);
+        };
+        match raw.to_ascii_lowercase().as_str() {
+            "true" | "yes" | "on" | "1" => Ok(true),
+            "false" | "no" | "off" | "0" => Ok(false),
+            _ => anyhow::bail!(
+                "Invalid {}.{} {raw:?} (from {source}): expected true/false, yes/no, on/off or 1/0",
+                key.section,
+                key.name
+            ),
+        }
+    }
+
+    pub fn api_base(&self, flag: Option<String>) -> Result<String> {
+        self.get(&API_BASE, flag)?.with_context(|| {
+            format!(
+                "No API base URL configured; pass --api-base, set API_BASE or add \
+                 `api_base` under [llm] in {}",
+                self.path.display()
+            )
+        })
+    }
+
+    /// Picks the API key source layer by layer: flags, the environment, the
+    /// profile, then the file, taking the first of key, file and command set
+    /// in a layer, so `API_KEY_CMD` beats `llm.api_key`; then the OS keyring.
+    fn api_key(&self, args: &LlmArgs, api_base: &str) -> ApiKey {
+        if let Some(key) = &args.api_key {
+            return ApiKey::Value(key.clone());
+        }
+        if let Some(path) = &args.api_key_file {
+            return ApiKey::File(path.clone());
+        }
+        if let Some(command) = &args.api_key_cmd {
+            return ApiKey::Command(command.clone());
+        }
+        let [keys, files, commands] =
+            [&API_KEY, &API_KEY_FILE, &API_KEY_CMD].map(|k| self.layers(k));
+        for ((key, file), command) in keys.into_iter().zip(files).zip(commands) {
+            if let Some((key, _)) = key {
+                return ApiKey::Value(key);
+            }
+            if let Some((path, _)) = file {
+                return ApiKey::File(path.into());
+            }
+            if let Some((command, _)) = command {
+                return ApiKey::Command(command);
+            }
+        }
+        ApiKey::Keyring(api_base.to_string())
+    }
+
+    pub fn llm(&self, args: &LlmArgs) -> Result<Llm> {
+        let api_base = self.api_base(args.api_base.clone())?;
+        Ok(Llm {
+            model: self.value(&MODEL, args.model.clone())?,
+            embedding_model: self.value(&EMBEDDING_MODEL, args.embedding_model.clone())?,
+            api_key: self.api_key(args, &api_base),
+            api_base,
+        })
+    }
+
+    /// Like `llm`, but without requiring an endpoint when the built-in hash
+    /// embeddings are used, since `recall` then never calls it.
+    pub fn embedding_llm(&self, args: &LlmArgs) -> Result<Llm> {
+        let embedding_model = self.value(&EMBEDDING_MODEL, args.embedding_model.clone())?;
+        if embedding_model != memory::HASH_MODEL {
+            return self.llm(args);
+        }
+        let api_base = self
+            .get(&API_BASE, args.api_base.clone())?
+            .unwrap_or_default();
+        Ok(Llm {
+            model: self.value(&MODEL, args.model.clone())?,
+            embedding_model,
+            api_key: self.api_key(args, &api_base),
+            api_base,
+        })
+    }
+
+    /// Prints every setting's effective value and source, masking secrets.
+    pub fn show(&self) {
+        if self.path.exists() {
+            println!("Config file: {}", self.path.display());
+        } else {
+            println!(
+                "Config file: {} (not present; create it with `config init`)",
+                self.path.display()
+            );
+        }
+        if let Some((name, _)) = &self.profile {
+            println!("Profile: {name}");
+        }
+        println!();
+        for key in KEYS {
+            let setting = format!("{}.{}", key.section, key.name);
+            match self.raw(key) {
+                Some((value, source)) => {
+                    let value = if key.secret {
+                        "********".to_string()
+                    } else {
+                        value
+                    };
+                    println!("{setting:<28} {value:<36} {source}");
+                }
+                None => println!("{setting:<28} {:<36} -", "(unset)"),
+            }
+        }
+        println!("\nCommand-line flags (e.g. --model) override these values.");
+    }
+}
+
+/// Writes a commented `config.toml` template.
+pub fn run_init(force: bool) -> Result<()> {
+    let path = config_path()?;
+    if path.exists() && !force {
+        anyhow::bail!(
+            "{} already exists; edit it, or pass --force to replace it",
+            path.display()
+        );
+    }
+    fs::write(&path, CONFIG_TEMPLATE)
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+    // It may end up holding an API key
+    restrict_permissions(&path)?;
+    println!("Wrote {}", path.display());
+    Ok(())
+}
@@ -0,0 +1,393 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local, Utc};
+use serde::{Deserialize, Serialize};
+use std::{
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering},
+        Mutex, PoisonError,
+    },
+};
+use tokio::sync::Notify;
+
+use crate::{append_log, data_dir, session::format_duration, CaptureEvent, LogEntry};
+
+// ------------ control socket -----------------------------------------------
+const CONTROL_SOCKET: &str = "control.sock";
+
+/// A command sent to the running `log`/`ambient` process, one JSON line per connection.
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "cmd", rename_all = "kebab-case")]
+pub enum Request {
+    /// Stop recording until resumed, or until `until` passes
+    Pause {
+        until: Option<DateTime<Utc>>,
+    },
+    Resume,
+    Status,
+    SummarizeNow,
+    ReloadConfig,
+    Stop,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Response {
+    ok: bool,
+    message: String,
+}
+
+#[derive(Clone, Copy, Default)]
+enum Capture {
+    #[default]
+    Recording,
+    /// Paused until `resume`, or until the given time
+    Paused(Option<DateTime<Utc>>),
+}
+
+/// State shared between the proxy's tasks and the control socket.
+pub struct DaemonState {
+    capture: Mutex<Capture>,
+    started: DateTime<Utc>,
+    /// Whether the ambient summarizer is running (`ambient` rather than `log`)
+    ambient: bool,
+    /// Squid's PID, 0 when it isn't running
+    squid_pid: AtomicU32,
+    /// When the current Squid process started, reset by each restart
+    squid_started: Mutex<Option<DateTime<Utc>>>,
+    entries_logged: AtomicUsize,
+    /// Summaries that failed and wait to be retried with the next window
+    queued_windows: AtomicUsize,
+    /// Queued entries dropped to keep the retry queue bounded
+    queued_dropped: AtomicUsize,
+    last_summary: Mutex<Option<DateTime<Utc>>>,
+    last_llm_error: Mutex<Option<String>>,
+    squid_restarts: AtomicUsize,
+    last_squid_failure: Mutex<Option<String>>,
+    reload: AtomicBool,
+    pub summarize_now: Notify,
+    pub shutdown: Notify,
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+impl DaemonState {
+    pub fn new(ambient: bool) -> Self {
+        Self {
+            capture: Mutex::default(),
+            started: Utc::now(),
+            ambient,
+            squid_pid: AtomicU32::new(0),
+            squid_started: Mutex::default(),
+            entries_logged: AtomicUsize::new(0),
+            queued_windows: AtomicUsize::new(0),
+            queued_dropped: AtomicUsize::new(0),
+            last_summary: Mutex::default(),
+            last_llm_error: Mutex::default(),
+            squid_restarts: AtomicUsize::new(0),
+            last_squid_failure: Mutex::default(),
+            reload: AtomicBool::new(false),
+            summarize_now: Notify::new(),
+            shutdown: Notify::new(),
+        }
+    }
+
+    /// Records a newly started Squid (or 0 once it has died), which also
+    /// restarts its uptime.
+    pub fn set_squid_pid(&self, pid: u32) {
+        self.squid_pid.store(pid, Ordering::Relaxed);
+        *lock(&self.squid_started) = (pid != 0).then(Utc::now);
+    }
+
+    pub fn record_logged(&self) {
+        self.entries_logged.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_summary(&self, result: Result<(), String>) {
+        match result {
+            Ok(()) => {
+                *lock(&self.last_summary) = Some(Utc::now());
+                *lock(&self.last_llm_error) = None;
+            }
+            Err(e) => *lock(&self.last_llm_error) = Some(e),
+        }
+    }
+
+    /// Records why Squid died; the supervisor restarts it afterwards.
+    pub fn record_squid_failure(&self, reason: String) {
+        self.squid_restarts.fetch_add(1, Ordering::Relaxed);
+        *lock(&self.last_squid_failure) = Some(reason);
+    }
+
+    /// Ambient windows waiting for a retry, counted for the persona that has
+    /// been failing longest.
+    pub fn set_queued_windows(&self, count: usize) {
+        self.queued_windows.store(count, Ordering::Relaxed);
+    }
+
+    /// Counts queued entries dropped because the retry queue was full.
+    pub fn record_queued_dropped(&self, count: usize) {
+        self.queued_dropped.fetch_add(count, Ordering::Relaxed);
+    }
+
+    /// Whether `reload-config` was requested since the last call.
+    pub fn take_reload(&self) -> bool {
+        self.reload.swap(false, Ordering::Relaxed)
+    }
+
+    /// Swaps in a new capture state and returns the previous one.
+    fn set_capture(&self, capture: Capture) -> Capture {
+        std::mem::replace(&mut *lock(&self.capture), capture)
+    }
+
+    fn pause(&self, until: Option<DateTime<Utc>>) -> Result<String> {
+        if matches!(self.set_capture(Capture::Paused(until)), Capture::Recording) {
+            append_log(&LogEntry::marker(CaptureEvent::Pause))?;
+        }
+        Ok(until.map_or_else(
+            || "Capture paused until `resume`".to_string(),
+            |t| {
+                format!(
+                    "Capture paused until {}",
+                    t.with_timezone(&Local).format("%H:%M")
+                )
+            },
+        ))
+    }
+
+    fn resume(&self) -> Result<String> {
+        if matches!(self.set_capture(Capture::Recording), Capture::Recording) {
+            return Ok("Capture is not paused".to_string());
+        }
+        append_log(&LogEntry::marker(CaptureEvent::Resume))?;
+        Ok("Capture resumed".to_string())
+    }
+
+    /// Ends a timed pause whose time has passed, recording the resume
+    /// marker at the moment the pause ran out. Called on every monitor tick,
+    /// so it doesn't wait for traffic to arrive.
+    pub fn expire_pause(&self) {
+        let until = {
+            let mut capture = lock(&self.capture);
+            match *capture {
+                Capture::Paused(Some(until)) if Utc::now() >= until => {
+                    *capture = Capture::Recording;
+                    until
+                }
+                _ => return,
+            }
+        };
+        let marker = LogEntry {
+            ts: until,
+            ..LogEntry::marker(CaptureEvent::Resume)
+        };
+        match append_log(&marker) {
+            Ok(()) => println!("Capture resumed"),
+            Err(e) => eprintln!("Failed to record resume: {e}"),
+        }
+    }
+
+    /// Whether traffic should be dropped right now.
+    pub fn is_paused(&self) -> bool {
+        matches!(*lock(&self.capture), Capture::Paused(_))
+    }
+
+    fn status(&self) -> String {
+        let local = |t: DateTime<Utc>| t.with_timezone(&Local).format("%Y-%m-%d %H:%M:%S");
+        let current = *lock(&self.capture);
+        let capture = match current {
+            Capture::Recording => "recording".to_string(),
+            Capture::Paused(None) => "paused".to_string(),
+            Capture::Paused(Some(until)) => format!("paused until {}", local(until)),
+        };
+        let pid = match self.squid_pid.load(Ordering::Relaxed) {
+            0 => "not running".to_string(),
+            pid => pid.to_string(),
+        };
+        let mut lines = vec![
+            format!("Mode: {}", if self.ambient { "ambient" } else { "log" }),
+            format!(
+                "Uptime: {} (since {})",
+                format_duration(Utc::now() - self.started),
+                local(self.started)
+            ),
+            format!("Squid PID: {pid}"),
+            format!(
+                "Squid uptime: {}",
+                lock(&self.squid_started).map_or_else(
+                    || "not running".to_string(),
+                    |t| format!("{} (since {})", format_duration(Utc::now() - t), local(t))
+                )
+            ),
+            format!(
+                "Squid crashes: {}{}",
+                self.squid_restarts.load(Ordering::Relaxed),
+                lock(&self.last_squid_failure)
+                    .as_deref()
+                    .map_or_else(String::new, |r| format!(" (last: {r})"))
+            ),
+            format!("Capture: {capture}"),
+            format!(
+                "Entries logged: {}",
+                self.entries_logged.load(Ordering::Relaxed)
+            ),
+        ];
+        if self.ambient {
+            lines.push(format!(
+                "Last summary: {}",
+                lock(&self.last_summary)
+                    .map_or_else(|| "never".to_string(), |t| local(t).to_string())
+            ));
+            lines.push(format!(
+                "Last LLM error: {}",
+                lock(&self.last_llm_error).as_deref().unwrap_or("none")
+            ));
+            lines.push(format!(
+                "Queued windows: {}",
+                self.queued_windows.load(Ordering::Relaxed)
+            ));
+            lines.push(format!(
+                "Queued entries dropped: {}",
+                self.queued_dropped.load(Ordering::Relaxed)
+            ));
+        }
+        lines.join("\n")
+    }
+
+    fn handle(&self, request: &Request) -> Result<String> {
+        match *request {
+            Request::Pause { until } => self.pause(until),
+            Request::Resume => self.resume(),
+            Request::Status => Ok(self.status()),
+            Request::SummarizeNow => {
+                if !self.ambient {
+                    anyhow::bail!("Summaries only run in `ambient` mode; use `analyze` instead");
+                }
+                self.summarize_now.notify_one();
+                Ok("Summarizing the current window".to_string())
+            }
+            Request::ReloadConfig => {
+                self.reload.store(true, Ordering::Relaxed);
+                Ok("Reloading domain rules, categories and Squid configuration".to_string())
+            }
+            Request::Stop => {
+                self.shutdown.notify_one();
+                Ok("Stopping proxy".to_string())
+            }
+        }
+    }
+}
+
+fn socket_path() -> Result<PathBuf> {
+    Ok(data_dir()?.join(CONTROL_SOCKET))
+}
+
+#[cfg(unix)]
+mod unix {
+    use super::{socket_path, DaemonState, Request, Response};
+    use crate::storage::restrict_permissions;
+    use anyhow::{Context, Result};
+    use std::{fs, io::Write, sync::Arc};
+    use tokio::{
+        io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+        net::{UnixListener, UnixStream},
+    };
+
+    /// Listens on the control socket until the task is aborted.
+    pub async fn serve(state: Arc<DaemonState>) -> Result<()> {
+        let path = socket_path()?;
+        if path.exists() {
+            if std::os::unix::net::UnixStream::connect(&path).is_ok() {
+                anyhow::bail!("Another proxy is already listening on {}", path.display());
+            }
+            fs::remove_file(&path)?;
+        }
+        let listener = UnixListener::bind(&path)
+            .with_context(|| format!("Failed to bind control socket {}", path.display()))?;
+        restrict_permissions(&path)?;
+
+        loop {
+            let (stream, _) = listener.accept().await?;
+            let state = Arc::clone(&state);
+            tokio::spawn(async move {
+                if let Err(e) = handle_connection(stream, &state).await {
+                    eprintln!("Control request failed: {e}");
+                }
+            });
+        }
+    }
+
+    async fn handle_connection(stream: UnixStream, state: &DaemonState) -> Result<()> {
+        let (read, mut write) = stream.into_split();
+        let mut line = String::new();
+        BufReader::new(read).read_line(&mut line).await?;
+        let result = serde_json::from_str::<Request>(&line)
+            .map_err(Into::into)
+            .and_then(|request| state.handle(&request));
+        let response = match result {
+            Ok(message) => Response { ok: true, message },
+            Err(e) => Response {
+                ok: false,
+                message: e.to_string(),
+            },
+        };
+        let mut reply = serde_json::to_vec(&response)?;
+        reply.push(b'\n');
+        write.write_all(&reply).await?;
+        Ok(())
+    }
+
+    pub fn send(request: &Request) -> Result<Response> {
+        let path = socket_path()?;
+        let mut stream = std::os::unix::net::UnixStream::connect(&path).with_context(|| {
+            format!(
+                "No running proxy found at {} (start `log` or `ambient` first)",
+                path.display()
+            )
+        })?;
+        writeln!(stream, "{}", serde_json::to_string(request)?)?;
+        let mut line = String::new();
+        std::io::BufRead::read_line(&mut std::io::BufReader::new(stream), &mut line)?;
+        Ok(serde_json::from_str(&line)?)
+    }
+}
+
+/// Starts the control socket for a running proxy.
+pub fn spawn(state: std::sync::Arc<DaemonState>) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        #[cfg(unix)]
+        let result = unix::serve(state).await;
+        #[cfg(not(unix))]
+        let result: Result<()> = {
+            drop(state);
+            Err(anyhow::anyhow!("not supported on this platform"))
+        };
+        if let Err(e) = result {
+            eprintln!("Control socket unavailable: {e}");
+        }
+    })
+}
+
+/// Removes the socket file once the proxy stops listening.
+pub fn cleanup() {
+    if let Ok(path) = socket_path() {
+        let _ = std::fs::remove_file(path);
+    }
+}
+
+/// Sends a command to the running proxy and returns its reply.
+pub fn send(request: &Request) -> Result<String> {
+    #[cfg(unix)]
+    let response = unix::send(request)?;
+    #[cfg(not(unix))]
+    let response: Response = {
+        let _ = request;
+        anyhow::bail!("The control socket is only supported on Unix");
+    };
+    if response.ok {
+        Ok(response.message)
+    } else {
+        Err(anyhow::anyhow!(response.message)).context("The proxy rejected the command")
+    }
+}
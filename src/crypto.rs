@@ -0,0 +1,540 @@
+use anyhow::{Context, Result};
+use argon2::Argon2;
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use chacha20poly1305::{
+    aead::{rand_core::RngCore, Aead, AeadCore, KeyInit, OsRng},
+    XChaCha20Poly1305, XNonce,
+};
+use clap::Args;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{
+    borrow::Cow,
+    fs,
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
+
+use crate::{
+    data_dir,
+    storage::{restrict_permissions, write_atomic},
+};
+
+// ------------ encryption at rest -------------------------------------------
+/// Marks a sealed record: `enc1:<base64 nonce || ciphertext>`. Anything else is plaintext,
+/// which an encrypted store only accepts from before encryption was enabled.
+const SEALED_PREFIX: &str = "enc1:";
+const KEY_META_FILE: &str = "encryption.json";
+/// Staged by `rekey` and swapped in for `KEY_META_FILE` once every data file is rewritten.
+const PENDING_META_FILE: &str = "encryption.pending.json";
+/// Known plaintext sealed into the metadata file to detect a wrong key.
+const KEY_CHECK: &str = "digital-twin-proxy";
+const NONCE_LEN: usize = 24;
+
+#[derive(Args, Default)]
+pub struct KeyArgs {
+    /// Encrypt stored logs and summaries with a key derived from this passphrase
+    #[arg(
+        long,
+        env = "ENCRYPTION_PASSPHRASE",
+        hide_env_values = true,
+        global = true
+    )]
+    passphrase: Option<String>,
+    /// Encrypt stored logs and summaries with the key in this file (see `rekey --new-key-file`)
+    #[arg(
+        long,
+        env = "ENCRYPTION_KEY_FILE",
+        global = true,
+        conflicts_with = "passphrase"
+    )]
+    key_file: Option<PathBuf>,
+}
+
+impl KeyArgs {
+    /// Uses `key_file` (from config.toml) when neither option was given.
+    pub fn or_key_file(&self, key_file: Option<PathBuf>) -> Self {
+        let fallback = if self.passphrase.is_none() {
+            key_file
+        } else {
+            None
+        };
+        Self {
+            passphrase: self.passphrase.clone(),
+            key_file: self.key_file.clone().or(fallback),
+        }
+    }
+}
+
+/// Records how the store is encrypted, next to the data it protects.
+#[derive(Serialize, Deserialize)]
+struct KeyMeta {
+    /// Argon2id salt for passphrase keys; absent for key files
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    salt: Option<String>,
+    check: String,
+    /// Plaintext records written before encryption was enabled are still
+    /// accepted; `rekey` seals them and clears this
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    legacy_plaintext: bool,
+}
+
+/// The target of a `rekey` that hasn't finished rewriting the data files.
+#[derive(Serialize, Deserialize)]
+struct PendingRekey {
+    /// Metadata of the new key, stored flat so the file can be renamed into
+    /// place as is; empty when decrypting
+    #[serde(flatten)]
+    target: Option<KeyMeta>,
+}
+
+enum KeyState {
+    /// No key configured and nothing encrypted yet
+    Disabled,
+    /// The store is encrypted but no key was given
+    Locked,
+    Unlocked(XChaCha20Poly1305),
+}
+
+static KEY: OnceLock<KeyState> = OnceLock::new();
+/// Set when an interrupted `rekey` left files under two different keys.
+static REKEY_PENDING: OnceLock<bool> = OnceLock::new();
+static LEGACY_PLAINTEXT: OnceLock<bool> = OnceLock::new();
+
+fn key_meta_path() -> Result<PathBuf> {
+    Ok(data_dir()?.join(KEY_META_FILE))
+}
+
+fn pending_meta_path() -> Result<PathBuf> {
+    Ok(data_dir()?.join(PENDING_META_FILE))
+}
+
+fn read_pending_meta() -> Result<Option<PendingRekey>> {
+    match fs::read_to_string(pending_meta_path()?) {
+        Ok(text) => Ok(Some(
+            serde_json::from_str(&text).context("Corrupt pending encryption metadata")?,
+        )),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn read_key_meta() -> Result<Option<KeyMeta>> {
+    match fs::read_to_string(key_meta_path()?) {
+        Ok(text) => Ok(Some(
+            serde_json::from_str(&text).context("Corrupt encryption metadata")?,
+        )),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn derive_key(passphrase: &str, salt: &[u8]) -> Result<XChaCha20Poly1305> {
+    let mut key = [0u8; 32];
+    Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|e| anyhow::anyhow!("Failed to derive key: {e}"))?;
+    Ok(XChaCha20Poly1305::new(&key.into()))
+}
+
+fn read_key_file(path: &Path) -> Result<XChaCha20Poly1305> {
+    let text = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read key file {}", path.display()))?;
+    let key: [u8; 32] = BASE64
+        .decode(text.trim())
+        .ok()
+        .and_then(|k| k.try_into().ok())
+        .ok_or_else(|| anyhow::anyhow!("{} is not a base64 32-byte key", path.display()))?;
+    Ok(XChaCha20Poly1305::new(&key.into()))
+}
+
+/// Writes a fresh random key to `path`, readable only by the owner.
+fn generate_key_file(path: &Path) -> Result<()> {
+    let key = XChaCha20Poly1305::generate_key(&mut OsRng);
+    fs::write(path, BASE64.encode(key) + "\n")
+        .with_context(|| format!("Failed to write key file {}", path.display()))?;
+    restrict_permissions(path)?;
+    println!("Generated a new key in {}", path.display());
+    Ok(())
+}
+
+/// Builds the cipher for a passphrase or key file, reusing `meta`'s salt when
+/// the store is already encrypted. Returns the metadata to store alongside.
+fn cipher_from(
+    passphrase: Option<&str>,
+    key_file: Option<&Path>,
+    meta: Option<&KeyMeta>,
+) -> Result<Option<(XChaCha20Poly1305, Option<String>)>> {
+    if let Some(path) = key_file {
+        return Ok(Some((read_key_file(path)?, None)));
+    }
+    let Some(passphrase) = passphrase else {
+        return Ok(None);
+    };
+    let salt = if let Some(meta) = meta {
+        meta.salt.clone().ok_or_else(|| {
+            anyhow::anyhow!("The data is encrypted with a key file; set ENCRYPTION_KEY_FILE")
+        })?
+    } else {
+        let mut salt = [0u8; 16];
+        OsRng.fill_bytes(&mut salt);
+        BASE64.encode(salt)
+    };
+    let cipher = derive_key(passphrase, &BASE64.decode(&salt)?)?;
+    Ok(Some((cipher, Some(salt))))
+}
+
+fn key_meta(cipher: &XChaCha20Poly1305, salt: Option<String>) -> Result<KeyMeta> {
+    Ok(KeyMeta {
+        salt,
+        check: seal_with(cipher, KEY_CHECK)?,
+        legacy_plaintext: false,
+    })
+}
+
+fn is_key_for(cipher: &XChaCha20Poly1305, meta: &KeyMeta) -> bool {
+    open_with(cipher, &meta.check).ok().as_deref() == Some(KEY_CHECK)
+}
+
+/// Sets up the process-wide key. The first run with a key enables encryption
+/// for everything written from then on; later runs must use the same key.
+pub fn init(args: &KeyArgs) -> Result<()> {
+    let mut meta = read_key_meta()?;
+    let pending = read_pending_meta()?.is_some();
+    let state = match cipher_from(
+        args.passphrase.as_deref(),
+        args.key_file.as_deref(),
+        meta.as_ref(),
+    )? {
+        Some((cipher, salt)) => {
+            match &meta {
+                Some(meta) => {
+                    if !is_key_for(&cipher, meta) {
+                        anyhow::bail!("Wrong encryption passphrase or key file");
+                    }
+                }
+                // An interrupted `rekey --decrypt` already removed the metadata
+                None if pending => {}
+                None => {
+                    let mut new = key_meta(&cipher, salt)?;
+                    new.legacy_plaintext = has_data()?;
+                    write_atomic(&key_meta_path()?, &serde_json::to_vec_pretty(&new)?)?;
+                    meta = Some(new);
+                }
+            }
+            KeyState::Unlocked(cipher)
+        }
+        None if meta.is_some() => KeyState::Locked,
+        None => KeyState::Disabled,
+    };
+    REKEY_PENDING.get_or_init(|| pending);
+    LEGACY_PLAINTEXT.get_or_init(|| meta.is_some_and(|m| m.legacy_plaintext));
+    KEY.set(state)
+        .map_err(|_| anyhow::anyhow!("Encryption already initialized"))
+}
+
+fn key() -> &'static KeyState {
+    KEY.get_or_init(|| KeyState::Disabled)
+}
+
+fn locked_error() -> anyhow::Error {
+    anyhow::anyhow!("Stored data is encrypted; set ENCRYPTION_PASSPHRASE or ENCRYPTION_KEY_FILE")
+}
+
+/// Fails early when the store is encrypted but no key was given, instead of
+/// every record silently failing to decode.
+pub fn require_key() -> Result<()> {
+    if REKEY_PENDING.get().copied().unwrap_or(false) {
+        anyhow::bail!(
+            "A rekey was interrupted; run `rekey` again with the same arguments to finish it"
+        );
+    }
+    require_unlocked()
+}
+
+fn require_unlocked() -> Result<()> {
+    match key() {
+        KeyState::Locked => Err(locked_error()),
+        _ => Ok(()),
+    }
+}
+
+fn seal_with(cipher: &XChaCha20Poly1305, plain: &str) -> Result<String> {
+    let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let mut sealed = nonce.to_vec();
+    sealed.extend(
+        cipher
+            .encrypt(&nonce, plain.as_bytes())
+            .map_err(|_| anyhow::anyhow!("Encryption failed"))?,
+    );
+    Ok(format!("{SEALED_PREFIX}{}", BASE64.encode(sealed)))
+}
+
+fn open_with(cipher: &XChaCha20Poly1305, sealed: &str) -> Result<String> {
+    let data = BASE64.decode(sealed.trim().trim_start_matches(SEALED_PREFIX))?;
+    if data.len() < NONCE_LEN {
+        anyhow::bail!("Truncated encrypted record");
+    }
+    let (nonce, ciphertext) = data.split_at(NONCE_LEN);
+    let plain = cipher
+        .decrypt(XNonce::from_slice(nonce), ciphertext)
+        .map_err(|_| anyhow::anyhow!("Encrypted record failed authentication"))?;
+    Ok(String::from_utf8(plain)?)
+}
+
+/// Encrypts a record if a key is configured; otherwise returns it unchanged.
+pub fn seal(plain: String) -> Result<String> {
+    match key() {
+        KeyState::Disabled => Ok(plain),
+        KeyState::Locked => Err(locked_error()),
+        KeyState::Unlocked(cipher) => seal_with(cipher, &plain),
+    }
+}
+
+fn is_sealed(text: &str) -> bool {
+    text.trim_start().starts_with(SEALED_PREFIX)
+}
+
+/// Decrypts a sealed record. Plaintext passes through when encryption is off,
+/// or until `rekey` has sealed the records written before it was turned on.
+pub fn open(text: &str) -> Result<Cow<'_, str>> {
+    if !is_sealed(text) {
+        return match key() {
+            KeyState::Disabled => Ok(Cow::Borrowed(text)),
+            _ if LEGACY_PLAINTEXT.get().copied().unwrap_or(false) => Ok(Cow::Borrowed(text)),
+            _ => Err(anyhow::anyhow!(
+                "Unencrypted record in an encrypted store; it was not written by this proxy"
+            )),
+        };
+    }
+    match key() {
+        KeyState::Unlocked(cipher) => Ok(Cow::Owned(open_with(cipher, text)?)),
+        _ => Err(locked_error()),
+    }
+}
+
+/// Serializes one NDJSON record, sealed when encryption is on.
+pub fn encode<T: Serialize>(value: &T) -> Result<String> {
+    seal(serde_json::to_string(value)?)
+}
+
+/// Parses one (possibly sealed) NDJSON record.
+pub fn decode<T: DeserializeOwned>(line: &str) -> Result<T> {
+    Ok(serde_json::from_str(&open(line)?)?)
+}
+
+// ------------ rekey -------------------------------------------------------
+#[derive(Args)]
+pub struct RekeyArgs {
+    /// New passphrase to encrypt with
+    #[arg(long, env = "NEW_ENCRYPTION_PASSPHRASE", hide_env_values = true)]
+    new_passphrase: Option<String>,
+    /// New key file to encrypt with (a random key is generated if it doesn't exist)
+    #[arg(long, conflicts_with = "new_passphrase")]
+    new_key_file: Option<PathBuf>,
+    /// Decrypt everything and turn encryption off
+    #[arg(long, conflicts_with_all = ["new_passphrase", "new_key_file"])]
+    decrypt: bool,
+}
+
+/// Files holding user data: NDJSON logs are sealed per line, summaries whole.
+fn data_files() -> Result<Vec<(PathBuf, bool)>> {
+    let dir = data_dir()?;
+    let mut files = Vec::new();
+    for sub in [dir.clone(), dir.join("segments")] {
+        let Ok(entries) = fs::read_dir(&sub) else {
+            continue;
+        };
+        for path in entries.filter_map(Result::ok).map(|e| e.path()) {
+            let name = path
+                .file_name()
+                .and_then(|n| n.to_str())
+                .unwrap_or_default();
+            if name.ends_with(".ndjson") {
+                files.push((path, true));
+            } else if name.starts_with("rolling_summary")
+                && path.extension().is_some_and(|ext| ext == "json")
+            {
+                files.push((path, false));
+            }
+        }
+    }
+    Ok(files)
+}
+
+/// Whether any data was written before encryption was turned on.
+fn has_data() -> Result<bool> {
+    Ok(data_files()?
+        .iter()
+        .any(|(path, _)| fs::metadata(path).is_ok_and(|m| m.len() > 0)))
+}
+
+/// Reseals one record under `new`. Records the interrupted run already
+/// moved to `new` are kept as they are.
+fn reseal(text: &str, new: Option<&XChaCha20Poly1305>) -> Result<String> {
+    let plain = match open(text) {
+        Ok(plain) => plain,
+        Err(e) => match new {
+            Some(cipher) if open_with(cipher, text).is_ok() => return Ok(text.to_string()),
+            None if !is_sealed(text) => return Ok(text.to_string()),
+            _ => return Err(e),
+        },
+    };
+    match new {
+        Some(cipher) => seal_with(cipher, &plain),
+        None => Ok(plain.into_owned()),
+    }
+}
+
+/// The key `rekey` moves to, and its metadata (`None` when decrypting).
+/// Resuming an interrupted run requires the same target it started with.
+fn rekey_target(
+    args: &RekeyArgs,
+    pending: Option<PendingRekey>,
+) -> Result<Option<(XChaCha20Poly1305, KeyMeta)>> {
+    let pending_meta = match pending {
+        Some(PendingRekey { target }) if target.is_some() != args.decrypt => target,
+        Some(_) => return Err(different_rekey()),
+        None => None,
+    };
+    if args.decrypt {
+        return Ok(None);
+    }
+    let (cipher, salt) = cipher_from(
+        args.new_passphrase.as_deref(),
+        args.new_key_file.as_deref(),
+        pending_meta.as_ref(),
+    )?
+    .ok_or_else(|| anyhow::anyhow!("Pass --new-passphrase, --new-key-file or --decrypt"))?;
+    let meta = match pending_meta {
+        Some(meta) if is_key_for(&cipher, &meta) => meta,
+        Some(_) => return Err(different_rekey()),
+        None => key_meta(&cipher, salt)?,
+    };
+    Ok(Some((cipher, meta)))
+}
+
+fn different_rekey() -> anyhow::Error {
+    anyhow::anyhow!(
+        "An interrupted rekey to a different key must be finished first; \
+         run `rekey` again with the arguments it was started with"
+    )
+}
+
+/// Re-encrypts every data file with a new key (or decrypts it), then replaces
+/// the key metadata. Stop the proxy first so no writes race the rewrite.
+///
+/// The new metadata is staged before any file changes and swapped in last, so
+/// an interrupted run can be finished by running it again with the same keys.
+pub fn run_rekey(args: &RekeyArgs) -> Result<()> {
+    require_unlocked()?;
+    if let Some(path) = &args.new_key_file {
+        if !path.exists() {
+            generate_key_file(path)?;
+        }
+    }
+    let pending = read_pending_meta()?;
+    let resuming = pending.is_some();
+    let (new_cipher, target) = rekey_target(args, pending)?.unzip();
+    let new_cipher = new_cipher.as_ref();
+
+    // Reseal everything in memory first so a bad record aborts before any file changes
+    let mut rewritten = Vec::new();
+    for (path, per_line) in data_files()? {
+        let text = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let data = if per_line {
+            let mut out = String::new();
+            for line in text.lines().filter(|l| !l.trim().is_empty()) {
+                out.push_str(
+                    &reseal(line, new_cipher).with_context(|| format!("In {}", path.display()))?,
+                );
+                out.push('\n');
+            }
+            out
+        } else {
+            reseal(&text, new_cipher).with_context(|| format!("In {}", path.display()))?
+        };
+        rewritten.push((path, data));
+    }
+    if !resuming {
+        write_atomic(
+            &pending_meta_path()?,
+            &serde_json::to_vec_pretty(&PendingRekey { target })?,
+        )?;
+    }
+    for (path, data) in &rewritten {
+        write_atomic(path, data.as_bytes())?;
+    }
+
+    let meta = key_meta_path()?;
+    if args.decrypt {
+        if meta.exists() {
+            fs::remove_file(meta)?;
+        }
+        fs::remove_file(pending_meta_path()?)?;
+    } else {
+        fs::rename(pending_meta_path()?, &meta)
+            .with_context(|| format!("Failed to replace {}", meta.display()))?;
+    }
+    println!(
+        "Rewrote {} files; {}",
+        rewritten.len(),
+        if args.decrypt {
+            "encryption is off"
+        } else {
+            "use the new key from now on"
+        }
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn random_cipher() -> XChaCha20Poly1305 {
+        XChaCha20Poly1305::new(&XChaCha20Poly1305::generate_key(&mut OsRng))
+    }
+
+    #[test]
+    fn seal_and_open_round_trip() -> Result<()> {
+        let cipher = random_cipher();
+        let plain = r#"{"url":"https://example.com/ü","ts":"2026-10-18T12:00:00Z"}"#;
+        let sealed = seal_with(&cipher, plain)?;
+        assert!(is_sealed(&sealed));
+        assert!(!sealed.contains("example.com"));
+        assert_eq!(open_with(&cipher, &sealed)?, plain);
+        // Fresh nonces, so equal records don't look equal on disk
+        assert_ne!(seal_with(&cipher, plain)?, sealed);
+        assert_eq!(open_with(&cipher, &seal_with(&cipher, "")?)?, "");
+        Ok(())
+    }
+
+    #[test]
+    fn open_rejects_the_wrong_key_and_damaged_records() -> Result<()> {
+        let cipher = random_cipher();
+        let sealed = seal_with(&cipher, "secret")?;
+        assert!(open_with(&random_cipher(), &sealed).is_err());
+
+        let mut data = BASE64.decode(&sealed[SEALED_PREFIX.len()..])?;
+        let last = data.len() - 1;
+        data[last] ^= 1;
+        let tampered = format!("{SEALED_PREFIX}{}", BASE64.encode(&data));
+        assert!(open_with(&cipher, &tampered).is_err());
+
+        let truncated = format!("{SEALED_PREFIX}{}", BASE64.encode([0u8; NONCE_LEN - 1]));
+        for bad in [truncated.as_str(), "enc1:", "enc1:not base64!"] {
+            assert!(open_with(&cipher, bad).is_err(), "{bad:?} was accepted");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn key_check_tells_keys_apart() -> Result<()> {
+        let cipher = random_cipher();
+        let meta = key_meta(&cipher, None)?;
+        assert!(is_key_for(&cipher, &meta));
+        assert!(!is_key_for(&random_cipher(), &meta));
+        Ok(())
+    }
+}
@@ -0,0 +1,360 @@
+use anyhow::{Context, Result};
+use async_openai::types::{ChatCompletionRequestUserMessageArgs, CreateChatCompletionRequestArgs};
+use std::{fs, path::Path};
+use tokio::{runtime::Runtime, time::Duration};
+
+use crate::{
+    check_squid_config, config::Llm, control, create_tools, find_squid_binary, port_in_use,
+    port_in_use_message, preview_squid_config, secrets::ApiKey, ProxyArgs, PROXY_PORT,
+    SQUID_CACHE_DIR, SQUID_PID_FILE,
+};
+
+// ------------ doctor -------------------------------------------------------
+const API_TIMEOUT: Duration = Duration::from_secs(10);
+/// Local models may need to load before answering the first request.
+const COMPLETION_TIMEOUT: Duration = Duration::from_mins(2);
+
+/// Collects check results and prints them as they come in.
+#[derive(Default)]
+struct Report {
+    problems: usize,
+    warnings: usize,
+}
+
+impl Report {
+    fn ok(check: &str, detail: &str) {
+        println!("[ok]   {check}: {detail}");
+    }
+
+    fn warn(&mut self, check: &str, detail: &str, fix: &str) {
+        self.warnings += 1;
+        println!("[warn] {check}: {detail}");
+        println!("       fix: {fix}");
+    }
+
+    fn fail(&mut self, check: &str, detail: &str, fix: &str) {
+        self.problems += 1;
+        println!("[FAIL] {check}: {detail}");
+        println!("       fix: {fix}");
+    }
+}
+
+const fn install_hint() -> &'static str {
+    if cfg!(target_os = "macos") {
+        "brew install squid"
+    } else if cfg!(target_os = "windows") {
+        "choco install squid"
+    } else {
+        "sudo apt install squid (Debian/Ubuntu), sudo dnf install squid (Fedora) or sudo pacman -S squid (Arch)"
+    }
+}
+
+fn check_squid(report: &mut Report, proxy: ProxyArgs) {
+    let Some(binary) = find_squid_binary() else {
+        report.fail("Squid binary", "not found", install_hint());
+        return;
+    };
+    Report::ok("Squid binary", &binary.display().to_string());
+
+    // Parse a rendered copy so the check doesn't rewrite squid.conf or create the CA.
+    let rendered = match preview_squid_config(proxy) {
+        Ok(rendered) => rendered,
+        Err(e) => {
+            report.warn(
+                "Squid config",
+                &format!("not checked: {e:#}"),
+                "run `digital-twin-proxy squid check` once the proxy has started",
+            );
+            return;
+        }
+    };
+    let config = std::env::temp_dir().join(format!(
+        "digital-twin-proxy-doctor-{}.conf",
+        std::process::id()
+    ));
+    if let Err(e) = fs::write(&config, rendered) {
+        report.fail(
+            "Squid config",
+            &format!("can't write {}: {e}", config.display()),
+            "check that the temp directory is writable",
+        );
+        return;
+    }
+    let result = check_squid_config(&binary, &config);
+    let _ = fs::remove_file(&config);
+    match result {
+        Ok(()) => Report::ok("Squid config", "squid -k parse accepts the rendered config"),
+        Err(e) => report.fail(
+            "Squid config",
+            &format!("{e:#}"),
+            "fix or remove the overlay, then run `digital-twin-proxy squid check`",
+        ),
+    }
+}
+
+/// Whether `pid` exists, by `kill(pid, 0)`; `None` when that can't be told.
+/// EPERM means the process exists but belongs to another user.
+#[cfg(unix)]
+fn process_alive(pid: u32) -> Option<bool> {
+    let pid = libc::pid_t::try_from(pid).ok()?;
+    // SAFETY: signal 0 only checks that the process exists; nothing is sent.
+    #[allow(unsafe_code)]
+    let result = unsafe { libc::kill(pid, 0) };
+    if result == 0 {
+        return Some(true);
+    }
+    match std::io::Error::last_os_error().raw_os_error() {
+        Some(libc::ESRCH) => Some(false),
+        Some(libc::EPERM) => Some(true),
+        _ => None,
+    }
+}
+
+#[cfg(not(unix))]
+const fn process_alive(_pid: u32) -> Option<bool> {
+    None
+}
+
+fn check_pid_file(report: &mut Report, proxy_running: bool) {
+    let Ok(contents) = fs::read_to_string(SQUID_PID_FILE) else {
+        Report::ok("Squid PID file", "none left behind");
+        return;
+    };
+    let Ok(pid) = contents.trim().parse::<u32>() else {
+        report.fail(
+            "Squid PID file",
+            &format!("{SQUID_PID_FILE} is unreadable"),
+            &format!("rm {SQUID_PID_FILE}"),
+        );
+        return;
+    };
+    match process_alive(pid) {
+        Some(false) => {
+            report.fail(
+                "Squid PID file",
+                &format!("{SQUID_PID_FILE} points at PID {pid}, which isn't running"),
+                &format!("rm {SQUID_PID_FILE}"),
+            );
+            return;
+        }
+        None => {
+            report.warn(
+                "Squid PID file",
+                &format!("{SQUID_PID_FILE} points at PID {pid}, which couldn't be checked"),
+                &format!("check with `ps -p {pid}`"),
+            );
+            return;
+        }
+        Some(true) => {}
+    }
+    if proxy_running {
+        Report::ok(
+            "Squid PID file",
+            &format!("PID {pid}, owned by the running proxy"),
+        );
+    } else {
+        report.warn(
+            "Squid PID file",
+            &format!("Squid from an earlier run is still running as PID {pid}"),
+            &format!("kill {pid}"),
+        );
+    }
+}
+
+fn check_port(report: &mut Report, proxy_running: bool) {
+    if !port_in_use(PROXY_PORT) {
+        Report::ok("Proxy port", &format!("{PROXY_PORT} is free"));
+    } else if proxy_running {
+        Report::ok(
+            "Proxy port",
+            &format!("{PROXY_PORT} is used by the running proxy"),
+        );
+    } else {
+        report.fail(
+            "Proxy port",
+            &format!("{PROXY_PORT} is already in use"),
+            &port_in_use_message(PROXY_PORT),
+        );
+    }
+}
+
+fn check_cache_dir(report: &mut Report) {
+    let dir = Path::new(SQUID_CACHE_DIR);
+    let chown = format!("sudo chown -R \"$USER\" {SQUID_CACHE_DIR} (or remove it)");
+    if !dir.exists() {
+        Report::ok(
+            "Squid cache",
+            &format!("{SQUID_CACHE_DIR} is created on first start"),
+        );
+    } else if !dir.is_dir() {
+        report.fail(
+            "Squid cache",
+            &format!("{SQUID_CACHE_DIR} is not a directory"),
+            &format!("rm {SQUID_CACHE_DIR}"),
+        );
+    } else {
+        let probe = dir.join(".doctor");
+        match fs::write(&probe, b"") {
+            Ok(()) => {
+                let _ = fs::remove_file(probe);
+                Report::ok("Squid cache", &format!("{SQUID_CACHE_DIR} is writable"));
+            }
+            Err(e) => report.fail(
+                "Squid cache",
+                &format!("{SQUID_CACHE_DIR} is not writable: {e}"),
+                &chown,
+            ),
+        }
+    }
+}
+
+/// Whether `api_base` answers at all; any HTTP status counts as reachable.
+async fn check_api_base(report: &mut Report, llm: &Llm, api_key: Option<&str>) -> bool {
+    let api_base = &llm.api_base;
+    let url = format!("{}/models", api_base.trim_end_matches('/'));
+    let client = match reqwest::Client::builder().timeout(API_TIMEOUT).build() {
+        Ok(client) => client,
+        Err(e) => {
+            report.fail("API endpoint", &e.to_string(), "check your TLS setup");
+            return false;
+        }
+    };
+    let mut request = client.get(&url);
+    if let Some(key) = api_key {
+        request = request.bearer_auth(key);
+    }
+    match request.send().await {
+        Ok(response) if response.status().as_u16() == 401 || response.status().as_u16() == 403 => {
+            report.fail(
+                "API endpoint",
+                &format!("{api_base} rejected the API key ({})", response.status()),
+                "set a valid key with API_KEY, --api-key-file, --api-key-cmd or `login`",
+            );
+            false
+        }
+        Ok(response) => {
+            Report::ok(
+                "API endpoint",
+                &format!("{api_base} answered ({})", response.status()),
+            );
+            true
+        }
+        Err(e) => {
+            report.fail(
+                "API endpoint",
+                &format!("{api_base} is unreachable: {e}"),
+                "start your model server (e.g. `ollama serve`) or fix API_BASE in .env",
+            );
+            false
+        }
+    }
+}
+
+/// Offers the model the summarizer's own tools and asks it to fetch a page,
+/// since `analyze` and `ambient` rely on tool calls to fetch pages.
+async fn check_tool_calling(report: &mut Report, llm: &Llm) -> Result<()> {
+    let model = &llm.model;
+    let client = llm.client().await?;
+    let request = CreateChatCompletionRequestArgs::default()
+        .model(model)
+        .messages(vec![ChatCompletionRequestUserMessageArgs::default()
+            .content(
+                "Call the fetch_page_content tool for https://example.com/. \
+                 Do not reply with text.",
+            )
+            .build()?
+            .into()])
+        .tools(create_tools()?)
+        .tool_choice("auto")
+        .build()?;
+
+    let fix = "pick a model with tool support (e.g. qwen3 or llama3.1 on Ollama) and set MODEL";
+    match tokio::time::timeout(COMPLETION_TIMEOUT, client.chat().create(request)).await {
+        Err(_) => report.fail(
+            "Tool calling",
+            &format!(
+                "{model} didn't answer within {}s",
+                COMPLETION_TIMEOUT.as_secs()
+            ),
+            "check that the model is pulled and the server isn't overloaded",
+        ),
+        Ok(Err(e)) => report.fail("Tool calling", &format!("{model}: {e}"), fix),
+        Ok(Ok(response)) => {
+            let called = response
+                .choices
+                .first()
+                .and_then(|c| c.message.tool_calls.as_ref())
+                .is_some_and(|calls| !calls.is_empty());
+            if called {
+                Report::ok("Tool calling", &format!("{model} calls tools"));
+            } else {
+                report.warn(
+                    "Tool calling",
+                    &format!(
+                        "{model} answered without calling the tool; page fetching may not work"
+                    ),
+                    fix,
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Checks the environment the proxy depends on and prints a fix for each problem.
+pub fn run_doctor(llm: Result<Llm>, proxy: ProxyArgs) -> Result<()> {
+    let mut report = Report::default();
+    let proxy_running = control::send(&control::Request::Status).is_ok();
+
+    check_squid(&mut report, proxy);
+    check_pid_file(&mut report, proxy_running);
+    check_port(&mut report, proxy_running);
+    check_cache_dir(&mut report);
+
+    match llm {
+        Err(e) => report.fail(
+            "API endpoint",
+            &e.to_string(),
+            "e.g. API_BASE=http://localhost:11434/v1 for Ollama",
+        ),
+        Ok(llm) => {
+            let rt = Runtime::new().context("Failed to create tokio runtime")?;
+            let api_key = match llm.api_key.resolve() {
+                Ok(key) => key,
+                Err(e) => {
+                    report.fail(
+                        "API key",
+                        &format!("{e:#}"),
+                        "fix the key file or command, or store a key with `login`",
+                    );
+                    None
+                }
+            };
+            rt.block_on(async {
+                if check_api_base(&mut report, &llm, api_key.as_deref()).await {
+                    // Reuse the key looked up above rather than running a key command again
+                    let llm = Llm {
+                        api_key: ApiKey::Resolved(api_key),
+                        ..llm
+                    };
+                    check_tool_calling(&mut report, &llm).await?;
+                }
+                anyhow::Ok(())
+            })?;
+        }
+    }
+
+    if report.problems > 0 {
+        anyhow::bail!(
+            "{} problem(s) and {} warning(s) found",
+            report.problems,
+            report.warnings
+        );
+    }
+    if report.warnings > 0 {
+        println!("No problems, {} warning(s)", report.warnings);
+    } else {
+        println!("Everything looks good");
+    }
+    Ok(())
+}
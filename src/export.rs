@@ -0,0 +1,190 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local, NaiveDate, Utc};
+use clap::ValueEnum;
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Write as _,
+    fs,
+    io::Write,
+    path::Path,
+};
+
+use crate::{
+    aggregate::{aggregate_visits, normalize_url, Visit},
+    read_log_since, read_summary_history,
+    rules::host_of,
+    LogEntry, SummaryRecord,
+};
+
+// ------------ export -------------------------------------------------------
+const TOP_DOMAINS: usize = 10;
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum ExportFormat {
+    /// One row per log entry
+    Csv,
+    /// Pretty-printed JSON with entries and summaries
+    Json,
+    /// One Markdown file per day (for Obsidian, Logseq, ...)
+    Markdown,
+}
+
+#[derive(Serialize)]
+struct JsonExport<'a> {
+    entries: Vec<&'a LogEntry>,
+    summaries: Vec<&'a SummaryRecord>,
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn write_csv(out: &mut dyn Write, entries: &[&LogEntry]) -> Result<()> {
+    writeln!(out, "ts,url,title,client,user,referer")?;
+    for e in entries {
+        let fields = [
+            e.ts.to_rfc3339(),
+            e.url.clone(),
+            e.title.clone().unwrap_or_default(),
+            e.client.clone().unwrap_or_default(),
+            e.user.clone().unwrap_or_default(),
+            e.referer.clone().unwrap_or_default(),
+        ];
+        let row: Vec<String> = fields.iter().map(|f| csv_field(f)).collect();
+        writeln!(out, "{}", row.join(","))?;
+    }
+    Ok(())
+}
+
+/// A Markdown link destination; in angle brackets `(`, `)` and spaces in the
+/// URL don't end the link.
+fn link_target(url: &str) -> String {
+    format!("<{}>", url.replace('<', "%3C").replace('>', "%3E"))
+}
+
+fn local_day(ts: DateTime<Utc>) -> NaiveDate {
+    ts.with_timezone(&Local).date_naive()
+}
+
+/// Renders one journal page: the last summary saved that day, top domains
+/// and the pages visited, with repeat visits collapsed.
+fn render_day(day: NaiveDate, entries: &[&LogEntry], summary: Option<&SummaryRecord>) -> String {
+    let mut page = format!("# {day}\n\n## Summary\n\n");
+    match summary {
+        Some(summary) => {
+            page.push_str(summary.text.trim());
+            page.push('\n');
+        }
+        None => page.push_str("_No summary recorded for this day._\n"),
+    }
+
+    let mut domains: HashMap<String, usize> = HashMap::new();
+    for host in entries.iter().filter_map(|e| host_of(&e.url)) {
+        *domains.entry(host).or_default() += 1;
+    }
+    let mut domains: Vec<(String, usize)> = domains.into_iter().collect();
+    domains.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+
+    page.push_str("\n## Top Domains\n\n| Domain | Requests |\n| --- | ---: |\n");
+    for (domain, count) in domains.iter().take(TOP_DOMAINS) {
+        let _ = writeln!(page, "| {domain} | {count} |");
+    }
+
+    let titles: HashMap<String, &str> = entries
+        .iter()
+        .filter_map(|e| Some((normalize_url(&e.url), e.title.as_deref()?)))
+        .collect();
+    let visits = entries.iter().map(|e| Visit {
+        url: e.url.clone(),
+        ts: e.ts,
+        dwell: None,
+    });
+    page.push_str("\n## Pages\n\n");
+    for visit in aggregate_visits(visits, None) {
+        let at = visit.first.with_timezone(&Local).format("%H:%M");
+        let label = titles
+            .get(&visit.url)
+            .copied()
+            .unwrap_or(&visit.url)
+            .replace(['[', ']'], "");
+        let _ = write!(page, "- {at} [{label}]({})", link_target(&visit.url));
+        if visit.count > 1 {
+            let _ = write!(page, " ×{}", visit.count);
+        }
+        page.push('\n');
+    }
+    page
+}
+
+fn write_journal(dir: &Path, entries: &[&LogEntry], summaries: &[&SummaryRecord]) -> Result<()> {
+    fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+
+    let mut days: BTreeMap<NaiveDate, Vec<&LogEntry>> = BTreeMap::new();
+    for entry in entries {
+        days.entry(local_day(entry.ts)).or_default().push(entry);
+    }
+    for (day, day_entries) in &days {
+        let summary = summaries
+            .iter()
+            .filter(|s| s.persona.is_none() && local_day(s.updated) == *day)
+            .max_by_key(|s| s.updated)
+            .copied();
+        let path = dir.join(format!("{day}.md"));
+        fs::write(&path, render_day(*day, day_entries, summary))
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+    }
+    println!("Wrote {} journal pages to {}", days.len(), dir.display());
+    Ok(())
+}
+
+pub fn run_export(
+    format: ExportFormat,
+    since: Option<DateTime<Utc>>,
+    until: Option<DateTime<Utc>>,
+    out: Option<&Path>,
+) -> Result<()> {
+    let in_window =
+        |ts: DateTime<Utc>| since.is_none_or(|s| ts >= s) && until.is_none_or(|u| ts < u);
+
+    let log = read_log_since(since)?;
+    let mut entries: Vec<&LogEntry> = log
+        .iter()
+        .filter(|e| !e.is_marker() && in_window(e.ts))
+        .collect();
+    entries.sort_by_key(|e| e.ts);
+    let history = read_summary_history()?;
+    let summaries: Vec<&SummaryRecord> = history.iter().filter(|s| in_window(s.updated)).collect();
+
+    match format {
+        ExportFormat::Markdown => {
+            let dir = out.ok_or_else(|| anyhow::anyhow!("--out <DIR> is required for markdown"))?;
+            write_journal(dir, &entries, &summaries)
+        }
+        ExportFormat::Csv => {
+            let mut writer = open_output(out)?;
+            write_csv(&mut writer, &entries)?;
+            Ok(writer.flush()?)
+        }
+        ExportFormat::Json => {
+            let mut writer = open_output(out)?;
+            serde_json::to_writer_pretty(&mut writer, &JsonExport { entries, summaries })?;
+            writeln!(writer)?;
+            Ok(writer.flush()?)
+        }
+    }
+}
+
+fn open_output(out: Option<&Path>) -> Result<Box<dyn Write>> {
+    Ok(match out {
+        Some(path) => Box::new(
+            fs::File::create(path)
+                .with_context(|| format!("Failed to create {}", path.display()))?,
+        ),
+        None => Box::new(std::io::stdout().lock()),
+    })
+}
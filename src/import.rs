@@ -0,0 +1,252 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use clap::ValueEnum;
+use directories::BaseDirs;
+use rusqlite::{Connection, OpenFlags};
+use std::{
+    collections::HashSet,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    append_logs, capture, categories::Taxonomy, data_dir, read_log, rules::host_of,
+    rules::DomainRules, LogEntry,
+};
+
+// ------------ history import -----------------------------------------------
+/// Microseconds between 1601-01-01 (Chromium's epoch) and 1970-01-01.
+const CHROMIUM_EPOCH_OFFSET_MICROS: i64 = 11_644_473_600_000_000;
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ImportFormat {
+    /// Firefox `places.sqlite`
+    Firefox,
+    /// Chromium-based browsers' `History` database (Chrome, Chromium, Brave, Edge)
+    Chromium,
+    /// HAR export from the browser's developer tools
+    Har,
+    /// mitmproxy flow dump (`mitmdump -w`)
+    MitmproxyFlows,
+    /// Squid access log in the default native format
+    SquidNative,
+    /// Apache/NGINX combined log format
+    CombinedLog,
+}
+
+impl ImportFormat {
+    /// The browser whose history database this format reads, if any.
+    const fn browser(self) -> Option<Browser> {
+        match self {
+            Self::Firefox => Some(Browser::Firefox),
+            Self::Chromium => Some(Browser::Chromium),
+            Self::Har | Self::MitmproxyFlows | Self::SquidNative | Self::CombinedLog => None,
+        }
+    }
+}
+
+/// The formats that are browser history databases, found and queried in place.
+#[derive(Clone, Copy)]
+enum Browser {
+    Firefox,
+    Chromium,
+}
+
+impl Browser {
+    /// Default profile databases, relative to the home directory.
+    const fn candidates(self) -> &'static [&'static str] {
+        match self {
+            Self::Firefox => &[
+                ".mozilla/firefox",
+                "Library/Application Support/Firefox/Profiles",
+                "snap/firefox/common/.mozilla/firefox",
+            ],
+            Self::Chromium => &[
+                ".config/google-chrome/Default/History",
+                ".config/chromium/Default/History",
+                ".config/BraveSoftware/Brave-Browser/Default/History",
+                ".config/microsoft-edge/Default/History",
+                "Library/Application Support/Google/Chrome/Default/History",
+                "Library/Application Support/Chromium/Default/History",
+                "Library/Application Support/BraveSoftware/Brave-Browser/Default/History",
+            ],
+        }
+    }
+
+    /// Finds the most recently used profile database for this browser.
+    fn discover(self) -> Result<PathBuf> {
+        let home = BaseDirs::new()
+            .ok_or_else(|| anyhow::anyhow!("Failed to find home directory"))?
+            .home_dir()
+            .to_path_buf();
+
+        let mut found = Vec::new();
+        for candidate in self.candidates() {
+            let path = home.join(candidate);
+            match self {
+                // Firefox keeps one places.sqlite per profile directory
+                Self::Firefox => {
+                    if let Ok(profiles) = fs::read_dir(&path) {
+                        found.extend(
+                            profiles
+                                .filter_map(Result::ok)
+                                .map(|p| p.path().join("places.sqlite"))
+                                .filter(|p| p.exists()),
+                        );
+                    }
+                }
+                Self::Chromium => {
+                    if path.exists() {
+                        found.push(path);
+                    }
+                }
+            }
+        }
+
+        found
+            .into_iter()
+            .max_by_key(|p| fs::metadata(p).and_then(|m| m.modified()).ok())
+            .ok_or_else(|| anyhow::anyhow!("No browser history found; pass the file path"))
+    }
+
+    const fn query(self) -> &'static str {
+        match self {
+            Self::Firefox => {
+                "SELECT p.url, p.title, v.visit_date FROM moz_historyvisits v \
+                 JOIN moz_places p ON p.id = v.place_id ORDER BY v.visit_date"
+            }
+            Self::Chromium => {
+                "SELECT u.url, u.title, v.visit_time FROM visits v \
+                 JOIN urls u ON u.id = v.url ORDER BY v.visit_time"
+            }
+        }
+    }
+
+    const fn timestamp(self, raw: i64) -> Option<DateTime<Utc>> {
+        match self {
+            Self::Firefox => DateTime::from_timestamp_micros(raw),
+            Self::Chromium => DateTime::from_timestamp_micros(raw - CHROMIUM_EPOCH_OFFSET_MICROS),
+        }
+    }
+}
+
+/// A copy of a history database, removed again when dropped.
+struct Snapshot {
+    dir: PathBuf,
+    db: PathBuf,
+}
+
+impl Drop for Snapshot {
+    fn drop(&mut self) {
+        fs::remove_dir_all(&self.dir).ok();
+    }
+}
+
+/// Copies the database (and its write-ahead log) aside, since the browser
+/// keeps the original locked while it's running.
+fn snapshot(db: &Path) -> Result<Snapshot> {
+    let dir = data_dir()?.join("import-snapshot");
+    // Left over if an earlier import was killed; a stale WAL would corrupt the copy
+    fs::remove_dir_all(&dir).ok();
+    fs::create_dir_all(&dir)?;
+    let snapshot = Snapshot {
+        db: dir.join("history.sqlite"),
+        dir,
+    };
+    fs::copy(db, &snapshot.db).with_context(|| format!("Failed to copy {}", db.display()))?;
+
+    let wal = PathBuf::from(format!("{}-wal", db.display()));
+    if wal.exists() {
+        fs::copy(&wal, format!("{}-wal", snapshot.db.display()))?;
+    }
+    Ok(snapshot)
+}
+
+fn read_history(db: &Path, browser: Browser) -> Result<Vec<LogEntry>> {
+    // Declared first so it outlives the connection and is removed on every path
+    let snapshot = snapshot(db)?;
+    let conn = Connection::open_with_flags(&snapshot.db, OpenFlags::SQLITE_OPEN_READ_WRITE)
+        .context("Failed to open history snapshot")?;
+    let mut stmt = conn
+        .prepare(browser.query())
+        .context("Unexpected history database schema (wrong --format?)")?;
+    let rows = stmt.query_map([], |row| {
+        Ok((
+            row.get::<_, String>(0)?,
+            row.get::<_, Option<String>>(1)?,
+            row.get::<_, i64>(2)?,
+        ))
+    })?;
+
+    let mut entries = Vec::new();
+    for row in rows {
+        let (url, title, raw_ts) = row?;
+        if !(url.starts_with("http://") || url.starts_with("https://")) {
+            continue;
+        }
+        let Some(ts) = browser.timestamp(raw_ts) else {
+            continue;
+        };
+        entries.push(LogEntry {
+            title: title.filter(|t| !t.is_empty()),
+            ..LogEntry::new(url, ts)
+        });
+    }
+    Ok(entries)
+}
+
+/// Appends imported entries that pass the domain rules and aren't already in
+/// the log (same URL and timestamp), so repeated imports are idempotent.
+pub fn import_entries(mut entries: Vec<LogEntry>, since: Option<DateTime<Utc>>) -> Result<()> {
+    let rules = DomainRules::load()?;
+    let mut seen: HashSet<(String, DateTime<Utc>)> =
+        read_log()?.into_iter().map(|e| (e.url, e.ts)).collect();
+
+    let total = entries.len();
+    entries.retain(|e| {
+        since.is_none_or(|since| e.ts >= since)
+            && host_of(&e.url).is_none_or(|h| rules.captures(&h))
+    });
+    let excluded = total - entries.len();
+    entries.retain(|e| seen.insert((e.url.clone(), e.ts)));
+    let duplicates = total - excluded - entries.len();
+    entries.sort_by_key(|e| e.ts);
+    Taxonomy::load()?.tag(&mut entries);
+
+    append_logs(&entries)?;
+    println!(
+        "Imported {} entries ({duplicates} already present, {excluded} excluded by window or domain rules)",
+        entries.len()
+    );
+    Ok(())
+}
+
+pub fn run_import(
+    path: Option<&Path>,
+    format: ImportFormat,
+    base_url: Option<&str>,
+    since: Option<DateTime<Utc>>,
+) -> Result<()> {
+    let path = match (path, format.browser()) {
+        (Some(path), _) => path.to_path_buf(),
+        (None, Some(browser)) => browser.discover()?,
+        (None, None) => {
+            anyhow::bail!("Pass the file to import; only browser history is found automatically")
+        }
+    };
+    println!("Importing from {}", path.display());
+    let read_text =
+        || fs::read_to_string(&path).with_context(|| format!("Failed to read {}", path.display()));
+
+    let entries = match format {
+        ImportFormat::Firefox => read_history(&path, Browser::Firefox)?,
+        ImportFormat::Chromium => read_history(&path, Browser::Chromium)?,
+        ImportFormat::Har => capture::parse_har(&read_text()?)?,
+        ImportFormat::MitmproxyFlows => capture::parse_mitmproxy_flows(
+            &fs::read(&path).with_context(|| format!("Failed to read {}", path.display()))?,
+        )?,
+        ImportFormat::SquidNative => capture::parse_squid_native(&read_text()?),
+        ImportFormat::CombinedLog => capture::parse_combined_log(&read_text()?, base_url),
+    };
+    import_entries(entries, since)
+}
@@ -1,42 +1,95 @@
 use anyhow::{Context, Result};
-use async_openai::{
-    config::OpenAIConfig,
-    types::{
-        ChatCompletionMessageToolCall, ChatCompletionRequestMessage,
-        ChatCompletionRequestSystemMessageArgs, ChatCompletionRequestToolMessageArgs,
-        ChatCompletionRequestUserMessageArgs, ChatCompletionTool, ChatCompletionToolType,
-        CreateChatCompletionRequestArgs, FunctionObjectArgs,
-    },
-    Client,
+use async_openai::types::{
+    ChatCompletionMessageToolCall, ChatCompletionRequestMessage,
+    ChatCompletionRequestSystemMessageArgs, ChatCompletionRequestToolMessageArgs,
+    ChatCompletionRequestUserMessageArgs, ChatCompletionTool, CreateChatCompletionRequestArgs,
 };
-use dotenvy::dotenv;
 use chrono::{DateTime, Duration as CDuration, Utc};
-use clap::{Parser, Subcommand};
+use clap::{Args, Parser, Subcommand};
 use directories::ProjectDirs;
+use dotenvy::dotenv;
 use scraper::{Html, Selector};
 use serde::{Deserialize, Serialize};
 use std::{
+    collections::{BTreeMap, VecDeque},
     fs::{self, OpenOptions},
-    io::{BufRead, BufReader, Write},
+    io::{BufRead, BufReader, ErrorKind, Write},
     path::{Path, PathBuf},
     process::{Child, Command, Stdio},
     sync::atomic::{AtomicBool, Ordering},
-    sync::Arc,
+    sync::{Arc, Mutex, PoisonError},
+    time::Instant,
 };
 use tokio::{runtime::Runtime, signal, task, time::Duration};
 
+mod aggregate;
+mod capture;
+mod categories;
+mod config;
+mod control;
+mod crypto;
+mod doctor;
+mod export;
+mod import;
+mod memory;
+mod pac;
+mod personas;
+mod rules;
+mod search;
+mod secrets;
+mod service;
+mod session;
+mod stats;
+mod storage;
+mod tls;
+mod tools;
+
+use categories::Taxonomy;
+use personas::Personas;
+use rules::{host_of, DomainRules};
+
 // ------------ constants ---------------------------------------------------
 const PROXY_PORT: u16 = 8888;
+const DEFAULT_PAC_PORT: u16 = 8889;
 const DEFAULT_MODEL: &str = "gpt-oss:20b";
+/// Built-in hash embeddings, so fetching a page never waits on `/embeddings`
+/// unless a real embedding model is configured.
+const DEFAULT_EMBEDDING_MODEL: &str = memory::HASH_MODEL;
+/// Pages the `search_memory` tool returns.
+const MEMORY_RESULTS: usize = 5;
 const LOG_FILE: &str = "log.ndjson";
 const SUMMARY_FILE: &str = "rolling_summary.json";
+const SUMMARY_HISTORY_FILE: &str = "summary_history.ndjson";
 const SQUID_LOG_PATH: &str = "/tmp/squid_access.log";
 const SQUID_CONFIG: &str = include_str!("../squid.conf");
+const SQUID_OVERLAY_FILE: &str = "squid.local.conf";
+const SQUID_OVERLAY_MARKER: &str = "# @overlay";
+const SQUID_SSL_BUMP_MARKER: &str = "# @ssl_bump";
+/// How long `squid -k shutdown` gets before Squid is killed.
+const SQUID_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
+const SQUID_CACHE_LOG: &str = "/tmp/ai-proxy-cache.log";
+const SQUID_PID_FILE: &str = "/tmp/ai-proxy-squid.pid";
+const SQUID_CACHE_DIR: &str = "/tmp/squid-cache";
+/// How long Squid gets to start accepting connections.
+const SQUID_READY_TIMEOUT: Duration = Duration::from_secs(20);
+/// Lines of stderr and `cache_log` kept for crash reports.
+const SQUID_TAIL_LINES: usize = 20;
+/// Consecutive crashes after which the supervisor stops restarting Squid.
+const SQUID_MAX_RESTARTS: u32 = 5;
+/// First restart delay; doubles with each consecutive crash.
+const SQUID_RESTART_BACKOFF: Duration = Duration::from_secs(2);
+/// Squid running this long counts as recovered, resetting the crash count.
+const SQUID_STABLE_AFTER: Duration = Duration::from_mins(1);
 
 // ------------ CLI ---------------------------------------------------------
 #[derive(Parser)]
 #[command(author, version, about = "Traffic logger & summarizer")]
 struct Cli {
+    #[command(flatten)]
+    keys: crypto::KeyArgs,
+    /// Use the settings under `[profiles.<name>]` in config.toml
+    #[arg(long, env = "CONFIG_PROFILE", global = true)]
+    profile: Option<String>,
     #[command(subcommand)]
     command: Commands,
 }
@@ -44,33 +97,233 @@ struct Cli {
 #[derive(Subcommand)]
 enum Commands {
     /// Start the proxy and log traffic only (no periodic summarization)
-    Log,
+    Log {
+        #[command(flatten)]
+        proxy: ProxyArgs,
+    },
     /// One-shot summarization of logged traffic since <duration>
     Analyze {
         #[arg(short, long)]
         since: String,
-        #[arg(short = 'x', long, env = "MAX_ANALYSIS_ITEMS", default_value_t = 500)]
-        max_items: usize, // safety cap
-        #[arg(short, long, env = "MODEL", default_value = DEFAULT_MODEL)]
-        model: String,
-        #[arg(long, env = "API_BASE")]
-        api_base: String,
-        #[arg(long, env = "API_KEY")]
-        api_key: Option<String>,
+        /// Only analyze traffic from this persona, client address or proxy-auth user
+        /// (an address or user in a persona selects the whole persona)
+        #[arg(short, long)]
+        client: Option<String>,
+        /// Safety cap on URLs analyzed (env: `MAX_ANALYSIS_ITEMS`, default 500)
+        #[arg(short = 'x', long)]
+        max_items: Option<usize>,
+        #[command(flatten)]
+        llm: config::LlmArgs,
+        /// Group URLs by their first N path segments instead of exactly (env: `ROLLUP_DEPTH`)
+        #[arg(long)]
+        rollup_depth: Option<usize>,
     },
     /// Start proxy + periodic summarization (background)
     Ambient {
-        #[arg(short, long, env = "AMBIENT_INTERVAL", default_value_t = 30)]
-        interval: u64, // seconds
-        #[arg(short, long, env = "MODEL", default_value = DEFAULT_MODEL)]
-        model: String,
-        #[arg(long, env = "API_BASE")]
-        api_base: String,
-        #[arg(long, env = "API_KEY")]
-        api_key: Option<String>,
+        /// Seconds between summaries (env: `AMBIENT_INTERVAL`, default 30)
+        #[arg(short, long)]
+        interval: Option<u64>,
+        /// Cap on URLs held per persona while failed summaries wait for a retry;
+        /// the oldest are dropped first (env: `MAX_ANALYSIS_ITEMS`, default 500)
+        #[arg(short = 'x', long)]
+        max_items: Option<usize>,
+        #[command(flatten)]
+        llm: config::LlmArgs,
+        /// Group URLs by their first N path segments instead of exactly (env: `ROLLUP_DEPTH`)
+        #[arg(long)]
+        rollup_depth: Option<usize>,
+        #[command(flatten)]
+        proxy: ProxyArgs,
+    },
+    /// Inspect and validate the generated Squid configuration
+    Squid {
+        #[command(subcommand)]
+        command: SquidCommand,
+    },
+    /// Manage the local CA used for TLS interception
+    Ca {
+        #[command(subcommand)]
+        command: CaCommand,
+    },
+    /// Generate or serve a proxy auto-config (PAC) file from the domain rules
+    Pac {
+        #[command(subcommand)]
+        command: PacCommand,
+    },
+    /// Import browser history or offline traffic captures into the log
+    Import {
+        /// File to read (browser formats default to the most recently used profile)
+        path: Option<PathBuf>,
+        #[arg(short, long, value_enum)]
+        format: import::ImportFormat,
+        /// Base URL for relative request paths in server-side combined logs
+        #[arg(long)]
+        base_url: Option<String>,
+        /// Only import visits since <duration> or RFC 3339 time
+        #[arg(short, long)]
+        since: Option<String>,
+    },
+    /// Export logged traffic and summaries as CSV, JSON or a Markdown journal
+    Export {
+        #[arg(short, long, value_enum)]
+        format: export::ExportFormat,
+        /// Start of the window (<duration> ago or RFC 3339 time)
+        #[arg(short, long)]
+        since: Option<String>,
+        /// End of the window (<duration> ago or RFC 3339 time)
+        #[arg(short, long)]
+        until: Option<String>,
+        /// Output file (CSV/JSON, default stdout) or directory (Markdown)
+        #[arg(short, long)]
+        out: Option<PathBuf>,
+    },
+    /// Search logged URLs, titles and fetched page text, grouped by browsing session
+    Search(search::SearchArgs),
+    /// Find pages fetched in earlier analyses that relate to a question
+    Recall(memory::RecallArgs),
+    /// Show top domains, activity by day and hour, new domains, error rates and bytes
+    Stats(stats::StatsArgs),
+    /// Compact the log: move past months into segments and roll up expired entries
+    Gc {
+        /// Days of raw entries to keep; older ones survive only as daily aggregates
+        /// (env: `RETAIN_RAW_DAYS`, default 30)
+        #[arg(long)]
+        keep_raw_days: Option<u32>,
+        /// Report what would change without rewriting anything
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Re-encrypt stored data with a new passphrase or key file, or decrypt it
+    Rekey(crypto::RekeyArgs),
+    /// Stop recording traffic in the running proxy (it keeps forwarding)
+    Pause {
+        /// Resume automatically after <duration> (e.g. 30m, 1h)
+        #[arg(long = "for", value_name = "DURATION")]
+        duration: Option<String>,
+    },
+    /// Resume recording after `pause`
+    Resume,
+    /// Show the running proxy's state: Squid PID, uptime, entries logged, summaries
+    Status,
+    /// Summarize the current window now instead of waiting for the interval (ambient only)
+    SummarizeNow,
+    /// Re-read domain rules, categories and the Squid overlay in the running proxy
+    ReloadConfig,
+    /// Stop the running proxy
+    Stop,
+    /// Run `ambient` as a systemd user service (launchd agent on macOS)
+    Service {
+        #[command(subcommand)]
+        command: ServiceCommand,
+    },
+    /// Check Squid, the proxy port, the cache directory and the LLM endpoint
+    Doctor {
+        #[command(flatten)]
+        llm: config::LlmArgs,
+    },
+    /// Store the API key for the configured endpoint in the OS keyring
+    Login {
+        /// Endpoint the key is for (env: `API_BASE`, config: `llm.api_base`)
+        #[arg(long)]
+        api_base: Option<String>,
+        /// Remove the stored key instead
+        #[arg(long)]
+        delete: bool,
+    },
+    /// Show or create the config file (config.toml in the config directory)
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+}
+
+#[derive(Subcommand)]
+enum SquidCommand {
+    /// Render the config (including the user overlay) and run `squid -k parse` on it
+    Check {
+        #[command(flatten)]
+        proxy: ProxyArgs,
+    },
+}
+
+#[derive(Subcommand)]
+enum ConfigCommand {
+    /// Print the effective settings and where each value came from
+    Show,
+    /// Write a commented config.toml template
+    Init {
+        /// Replace an existing config.toml
+        #[arg(long)]
+        force: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum ServiceCommand {
+    /// Install and start the service with the current environment and `.env` settings
+    Install,
+    /// Stop and remove the service
+    Uninstall,
+    /// Show whether the service is installed and running
+    Status,
+}
+
+#[derive(Subcommand)]
+enum CaCommand {
+    /// Print the CA certificate path, fingerprint and browser import instructions
+    Show,
+    /// Replace the CA with a freshly generated one
+    Regenerate,
+}
+
+#[derive(Subcommand)]
+enum PacCommand {
+    /// Print the PAC file
+    Show {
+        /// Proxy address the browser should use
+        #[arg(long, default_value = "127.0.0.1")]
+        proxy_host: String,
+    },
+    /// Serve the PAC file over HTTP without starting the proxy
+    Serve {
+        #[arg(short, long, default_value_t = DEFAULT_PAC_PORT)]
+        port: u16,
+        /// Proxy address the browser should use
+        #[arg(long, default_value = "127.0.0.1")]
+        proxy_host: String,
     },
 }
 
+#[derive(Args, Clone, Copy, Default)]
+struct ProxyArgs {
+    /// Decrypt HTTPS with the local CA so full URLs are logged (hosts matching
+    /// deny/no-bump domain rules are never decrypted) (env: `TLS_INTERCEPT`)
+    #[arg(long, overrides_with = "no_tls_intercept")]
+    tls_intercept: bool,
+    /// Don't decrypt HTTPS, even if `TLS_INTERCEPT` or config.toml turns it on
+    #[arg(long, overrides_with = "tls_intercept")]
+    no_tls_intercept: bool,
+    /// Also serve a PAC file on this port, at `/proxy.pac` (env: `PAC_PORT`)
+    #[arg(long)]
+    pac_port: Option<u16>,
+}
+
+impl ProxyArgs {
+    /// Fills in what wasn't given as a flag from the environment and config.toml.
+    fn resolve(self, settings: &config::Settings) -> Result<Self> {
+        let flag = if self.no_tls_intercept {
+            Some(false)
+        } else {
+            self.tls_intercept.then_some(true)
+        };
+        Ok(Self {
+            tls_intercept: settings.switch(&config::TLS_INTERCEPT, flag)?,
+            no_tls_intercept: false,
+            pac_port: settings.get(&config::PAC_PORT, self.pac_port)?,
+        })
+    }
+}
+
 // ------------ helpers -----------------------------------------------------
 fn project_dirs() -> Result<ProjectDirs> {
     ProjectDirs::from("rs", "ai-proxy", "ai-proxy")
@@ -87,48 +340,209 @@ fn log_path() -> Result<PathBuf> {
     Ok(data_dir()?.join(LOG_FILE))
 }
 
-fn summary_path() -> Result<PathBuf> {
-    Ok(data_dir()?.join(SUMMARY_FILE))
+fn summary_path(persona: Option<&str>) -> Result<PathBuf> {
+    let path = data_dir()?.join(SUMMARY_FILE);
+    Ok(match persona {
+        Some(name) => path.with_extension(format!("{name}.json")),
+        None => path,
+    })
+}
+
+fn config_dir() -> Result<PathBuf> {
+    let d = project_dirs()?.config_dir().to_path_buf();
+    fs::create_dir_all(&d)?;
+    Ok(d)
+}
+
+fn squid_overlay_path() -> Result<PathBuf> {
+    Ok(config_dir()?.join(SQUID_OVERLAY_FILE))
+}
+
+fn render_squid_config(bump: Option<&tls::BumpConfig>) -> Result<String> {
+    let overlay_path = squid_overlay_path()?;
+    let overlay = match fs::read_to_string(&overlay_path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => {
+            return Err(e).with_context(|| {
+                format!("Failed to read squid overlay {}", overlay_path.display())
+            })
+        }
+    };
+
+    let mut rendered = vec![
+        "# Generated by digital-twin-proxy - local edits to this file are overwritten.".to_string(),
+        format!(
+            "# Put custom directives in {} instead.",
+            overlay_path.display()
+        ),
+        String::new(),
+    ];
+    for line in SQUID_CONFIG.lines() {
+        if line.trim() == SQUID_SSL_BUMP_MARKER {
+            if let Some(bump) = bump {
+                rendered.push(bump.rules.clone());
+            }
+        } else if line.starts_with("http_port ") && bump.is_some() {
+            rendered.extend(bump.map(|b| b.http_port.clone()));
+        } else if line.trim() != SQUID_OVERLAY_MARKER {
+            rendered.push(line.to_string());
+        } else if !overlay.trim().is_empty() {
+            rendered.push(format!("# --- begin {} ---", overlay_path.display()));
+            rendered.push(overlay.trim_end().to_string());
+            rendered.push("# --- end overlay ---".to_string());
+        }
+    }
+    rendered.push(String::new());
+    Ok(rendered.join("\n"))
+}
+
+/// The config `squid_config_path` would write, without creating the local CA
+/// or writing anything, for `doctor`.
+fn preview_squid_config(proxy: ProxyArgs) -> Result<String> {
+    let bump = if proxy.tls_intercept {
+        Some(tls::preview_bump_config(&DomainRules::read()?)?)
+    } else {
+        None
+    };
+    render_squid_config(bump.as_ref())
 }
 
-fn squid_config_path() -> Result<PathBuf> {
+fn squid_config_path(proxy: ProxyArgs) -> Result<PathBuf> {
     let config_path = data_dir()?.join("squid.conf");
+    let bump = if proxy.tls_intercept {
+        Some(tls::bump_config(&DomainRules::load()?)?)
+    } else {
+        None
+    };
+    let rendered = render_squid_config(bump.as_ref())?;
 
-    // Write the embedded config if it doesn't exist or is outdated
-    if !config_path.exists() || config_needs_update(&config_path)? {
-        fs::write(&config_path, SQUID_CONFIG).context("Failed to write squid configuration")?;
+    // Write the rendered config if it doesn't exist or is outdated
+    if !config_path.exists() || config_needs_update(&config_path, &rendered)? {
+        fs::write(&config_path, rendered).context("Failed to write squid configuration")?;
     }
 
     Ok(config_path)
 }
 
-fn config_needs_update(path: &Path) -> Result<bool> {
-    // Check if the existing config is different from the embedded one
+fn config_needs_update(path: &Path, rendered: &str) -> Result<bool> {
+    // Check if the existing config is different from the rendered one
     let existing = fs::read_to_string(path)?;
-    Ok(existing != SQUID_CONFIG)
+    Ok(existing != rendered)
 }
 
 // ------------ logging -----------------------------------------------------
-#[derive(Serialize, Deserialize)]
+/// Capture state changes recorded in the log, so summaries can tell a
+/// deliberate pause from an idle stretch.
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum CaptureEvent {
+    Pause,
+    Resume,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
 struct LogEntry {
+    /// Requested URL; empty for capture markers
+    #[serde(default, skip_serializing_if = "String::is_empty")]
     url: String,
     ts: DateTime<Utc>,
+    /// Client address (`%>a`) that made the request
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    client: Option<String>,
+    /// Proxy-auth username (`%un`), when Squid authentication is configured
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    user: Option<String>,
+    /// Referer header, used to stitch requests into browsing sessions
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    referer: Option<String>,
+    /// Page title, when known (e.g. imported from browser history)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    title: Option<String>,
+    /// HTTP status code, when the source records one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    status: Option<u16>,
+    /// Response size in bytes, when the source records one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    bytes: Option<u64>,
+    /// Topic category from the taxonomy at capture time (see `categories.conf`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    category: Option<String>,
+    /// Set on pause/resume markers instead of a URL
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    event: Option<CaptureEvent>,
 }
 
-fn append_log(url: &str) -> Result<()> {
-    let entry = LogEntry {
-        url: url.to_string(),
-        ts: Utc::now(),
-    };
+impl LogEntry {
+    const fn new(url: String, ts: DateTime<Utc>) -> Self {
+        Self {
+            url,
+            ts,
+            client: None,
+            user: None,
+            referer: None,
+            title: None,
+            status: None,
+            bytes: None,
+            category: None,
+            event: None,
+        }
+    }
+
+    fn marker(event: CaptureEvent) -> Self {
+        Self {
+            event: Some(event),
+            ..Self::new(String::new(), Utc::now())
+        }
+    }
+
+    const fn is_marker(&self) -> bool {
+        self.event.is_some()
+    }
+}
+
+fn append_log(entry: &LogEntry) -> Result<()> {
+    append_logs(std::slice::from_ref(entry))
+}
+
+fn append_logs(entries: &[LogEntry]) -> Result<()> {
+    // `gc` may be replacing the file
+    let _lock = storage::lock_appends()?;
     let mut file = OpenOptions::new()
         .create(true)
         .append(true)
         .open(log_path()?)?;
-    serde_json::to_writer(&mut file, &entry)?;
-    writeln!(file)?;
+    for entry in entries {
+        writeln!(file, "{}", crypto::encode(entry)?)?;
+    }
     Ok(())
 }
 
+/// Reads every parseable entry from the log; a missing log is empty.
+fn read_log() -> Result<Vec<LogEntry>> {
+    read_log_since(None)
+}
+
+/// Reads parseable entries at or after `since`, skipping monthly segments
+/// that end before it.
+fn read_log_since(since: Option<DateTime<Utc>>) -> Result<Vec<LogEntry>> {
+    crypto::require_key()?;
+    let mut entries = Vec::new();
+    for path in storage::log_files_since(since)? {
+        let Ok(file) = fs::File::open(path) else {
+            continue;
+        };
+        entries.extend(
+            BufReader::new(file)
+                .lines()
+                .map_while(Result::ok)
+                .filter_map(|line| crypto::decode::<LogEntry>(&line).ok())
+                .filter(|e| since.is_none_or(|s| e.ts >= s)),
+        );
+    }
+    Ok(entries)
+}
+
 // ------------ squid management -------------------------------------------
 fn find_squid_binary() -> Option<PathBuf> {
     // Common locations for squid binary
@@ -197,19 +611,204 @@ fn print_install_instructions() {
     eprintln!();
 }
 
+/// Runs `squid -k parse` against the generated config and fails with Squid's
+/// own diagnostics if the config (usually the user overlay) is invalid.
+fn check_squid_config(squid_binary: &Path, config_path: &Path) -> Result<()> {
+    let output = Command::new(squid_binary)
+        .arg("-k")
+        .arg("parse")
+        .arg("-f")
+        .arg(config_path)
+        .arg("-n")
+        .arg("aiproxy")
+        .output()
+        .context("Failed to run squid -k parse")?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        anyhow::bail!(
+            "Squid rejected {} (overlay: {}):\n{}",
+            config_path.display(),
+            squid_overlay_path()?.display(),
+            stderr.trim_end()
+        );
+    }
+    Ok(())
+}
+
+/// Runs `squid -k <signal>` against the instance started with `config_path`.
+fn signal_squid(squid_binary: &Path, config_path: &Path, signal: &str) -> Result<()> {
+    let output = Command::new(squid_binary)
+        .arg("-k")
+        .arg(signal)
+        .arg("-f")
+        .arg(config_path)
+        .arg("-n")
+        .arg("aiproxy")
+        .output()
+        .with_context(|| format!("Failed to run squid -k {signal}"))?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "squid -k {signal} failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim_end()
+        );
+    }
+    Ok(())
+}
+
+/// Re-renders the config and tells the running Squid to re-read it.
+fn reconfigure_squid(proxy: ProxyArgs) -> Result<()> {
+    let squid_binary =
+        find_squid_binary().ok_or_else(|| anyhow::anyhow!("Squid is not installed"))?;
+    let config_path = squid_config_path(proxy)?;
+    check_squid_config(&squid_binary, &config_path)?;
+    signal_squid(&squid_binary, &config_path, "reconfigure")
+}
+
+/// Sends SIGTERM, the fallback when `squid -k shutdown` can't reach Squid.
+fn terminate(pid: u32) {
+    #[cfg(unix)]
+    let _ = Command::new("kill")
+        .arg("-TERM")
+        .arg(pid.to_string())
+        .status();
+    #[cfg(not(unix))]
+    let _ = pid;
+}
+
+/// Tail of Squid's stderr, kept for crash reports.
+type StderrTail = Arc<Mutex<VecDeque<String>>>;
+
 struct SquidProcess {
     child: Child,
+    binary: PathBuf,
+    config: PathBuf,
     running: Arc<AtomicBool>,
+    stderr: StderrTail,
+    started: Instant,
+}
+
+/// Reads a child's stderr on a thread, keeping the last `SQUID_TAIL_LINES`
+/// lines. Draining it also keeps Squid from blocking on a full pipe.
+fn capture_stderr(child: &mut Child) -> StderrTail {
+    let tail: StderrTail = Arc::default();
+    if let Some(stderr) = child.stderr.take() {
+        let tail = Arc::clone(&tail);
+        std::thread::spawn(move || {
+            for line in BufReader::new(stderr).lines().map_while(Result::ok) {
+                let mut tail = tail.lock().unwrap_or_else(PoisonError::into_inner);
+                if tail.len() == SQUID_TAIL_LINES {
+                    tail.pop_front();
+                }
+                tail.push_back(line);
+            }
+        });
+    }
+    tail
+}
+
+/// The last `n` lines of a text file, or none if it can't be read.
+fn tail_lines(path: &Path, n: usize) -> Vec<String> {
+    let Ok(text) = fs::read_to_string(path) else {
+        return Vec::new();
+    };
+    let lines: Vec<&str> = text.lines().collect();
+    lines[lines.len().saturating_sub(n)..]
+        .iter()
+        .map(|l| (*l).to_string())
+        .collect()
+}
+
+/// Whether something already listens on `port`, checked by trying to bind
+/// it the way Squid will (all interfaces).
+fn port_in_use(port: u16) -> bool {
+    std::net::TcpListener::bind(("0.0.0.0", port)).is_err_and(|e| e.kind() == ErrorKind::AddrInUse)
+}
+
+fn port_in_use_message(port: u16) -> String {
+    format!(
+        "Port {port} is already in use by another process, often a system Squid or an \
+         earlier ai-proxy that didn't exit. Find it with `lsof -i :{port}` or \
+         `ss -ltnp 'sport = :{port}'`, then stop it (e.g. `sudo systemctl stop squid`)."
+    )
+}
+
+/// Lines Squid writes when it can't open its listening port.
+const SQUID_BIND_ERRORS: &[&str] = &[
+    "Cannot bind socket",
+    "Address already in use",
+    "Unable to open HTTP Socket",
+];
+
+/// Waits until Squid accepts connections on `PROXY_PORT`, failing early if
+/// it exits or reports a bind error in `cache_log` or on stderr.
+fn wait_until_ready(child: &mut Child, stderr: &StderrTail, cache_log_start: u64) -> Result<()> {
+    let deadline = Instant::now() + SQUID_READY_TIMEOUT;
+    loop {
+        let mut output: Vec<String> = stderr
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .iter()
+            .cloned()
+            .collect();
+        if let Ok(text) = fs::read(SQUID_CACHE_LOG) {
+            let new = text
+                .get(usize::try_from(cache_log_start).unwrap_or(0)..)
+                .unwrap_or_default();
+            output.extend(String::from_utf8_lossy(new).lines().map(str::to_string));
+        }
+        let bind_error = output
+            .iter()
+            .find(|line| SQUID_BIND_ERRORS.iter().any(|e| line.contains(e)));
+
+        if let Some(status) = child.try_wait()? {
+            // Give the stderr reader a moment to catch the last lines
+            std::thread::sleep(Duration::from_millis(100));
+            anyhow::bail!(
+                "Squid process exited immediately with status: {status:?}\nStderr: {}",
+                stderr
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .iter()
+                    .cloned()
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            );
+        }
+        if let Some(line) = bind_error {
+            let hint = if line.contains("Address already in use") {
+                port_in_use_message(PROXY_PORT)
+            } else {
+                format!("See {SQUID_CACHE_LOG} for details.")
+            };
+            anyhow::bail!(
+                "Squid couldn't open port {PROXY_PORT}: {}\n{hint}",
+                line.trim()
+            );
+        }
+        if std::net::TcpStream::connect(("127.0.0.1", PROXY_PORT)).is_ok() {
+            return Ok(());
+        }
+        if Instant::now() >= deadline {
+            anyhow::bail!(
+                "Squid didn't start listening on port {PROXY_PORT} within {}s; see {SQUID_CACHE_LOG}",
+                SQUID_READY_TIMEOUT.as_secs()
+            );
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    }
 }
 
 impl SquidProcess {
-    fn start() -> Result<Self> {
+    fn start(proxy: ProxyArgs) -> Result<Self> {
         let squid_binary = find_squid_binary().ok_or_else(|| {
             print_install_instructions();
             anyhow::anyhow!("Squid is not installed")
         })?;
 
-        let config_path = squid_config_path().context("Failed to setup squid configuration")?;
+        let config_path =
+            squid_config_path(proxy).context("Failed to setup squid configuration")?;
+        check_squid_config(&squid_binary, &config_path)?;
 
         println!("Starting Squid proxy on port {PROXY_PORT}...");
 
@@ -231,101 +830,305 @@ impl SquidProcess {
             }
         }
 
+        let (child, stderr) = Self::spawn_child(&squid_binary, &config_path)?;
+        Ok(Self {
+            child,
+            binary: squid_binary,
+            config: config_path,
+            running: Arc::new(AtomicBool::new(true)),
+            stderr,
+            started: Instant::now(),
+        })
+    }
+
+    fn spawn_child(squid_binary: &Path, config_path: &Path) -> Result<(Child, StderrTail)> {
+        if port_in_use(PROXY_PORT) {
+            anyhow::bail!("{}", port_in_use_message(PROXY_PORT));
+        }
+        // Only lines Squid writes from now on count towards readiness
+        let cache_log_start = fs::metadata(SQUID_CACHE_LOG).map_or(0, |m| m.len());
+
         // Start squid with our custom config
-        let mut child = Command::new(&squid_binary)
+        let mut child = Command::new(squid_binary)
             .env("SQUID_CONF_DIR", data_dir()?.to_str().unwrap_or("/tmp"))
             .arg("-N") // Don't run as daemon
             .arg("-f") // Config file
-            .arg(&config_path)
+            .arg(config_path)
             .arg("-d") // Debug level
             .arg("1") // Minimal debug output
             .arg("-n") // Service name
             .arg("aiproxy") // Unique service name to avoid conflicts
-            .stdout(Stdio::piped())
+            .stdout(Stdio::null())
             .stderr(Stdio::piped())
             .spawn()
             .context("Failed to start Squid process")?;
+        let stderr = capture_stderr(&mut child);
+
+        if let Err(e) = wait_until_ready(&mut child, &stderr, cache_log_start) {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(e);
+        }
+        println!("Proxy listening on 127.0.0.1:{PROXY_PORT}");
+        Ok((child, stderr))
+    }
 
-        let running = Arc::new(AtomicBool::new(true));
+    /// Starts a fresh Squid in place of one that died, keeping the shared
+    /// `running` flag so the log monitor carries on.
+    fn restart(&mut self) -> Result<()> {
+        let (child, stderr) = Self::spawn_child(&self.binary, &self.config)?;
+        self.child = child;
+        self.stderr = stderr;
+        self.started = Instant::now();
+        Ok(())
+    }
 
-        // Give Squid a moment to start and check if it's still running
-        std::thread::sleep(std::time::Duration::from_secs(2));
+    /// Why Squid exited: its status plus the last stderr and `cache_log` lines.
+    fn failure_reason(&self, status: std::process::ExitStatus) -> String {
+        let mut lines: Vec<String> = self
+            .stderr
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .iter()
+            .cloned()
+            .collect();
+        lines.extend(tail_lines(Path::new(SQUID_CACHE_LOG), SQUID_TAIL_LINES));
+        // FATAL lines say what went wrong; otherwise show the most recent output
+        let detail = lines
+            .iter()
+            .rev()
+            .find(|l| l.contains("FATAL"))
+            .or_else(|| lines.last())
+            .map_or_else(String::new, |l| format!(": {}", l.trim()));
+        format!("Squid exited with {status}{detail}")
+    }
 
-        // Check if the process is still running
-        match child.try_wait() {
-            Ok(Some(status)) => {
-                // Process has exited
-                let mut stderr = String::new();
-                if let Some(mut stderr_stream) = child.stderr.take() {
-                    use std::io::Read;
-                    let _ = stderr_stream.read_to_string(&mut stderr);
-                }
-                anyhow::bail!(
-                    "Squid process exited immediately with status: {:?}\nStderr: {}",
-                    status,
-                    stderr
-                );
-            }
-            Ok(None) => {
-                // Still running, good!
-                println!("Proxy listening on 127.0.0.1:{PROXY_PORT}");
+    fn pid(&self) -> u32 {
+        self.child.id()
+    }
+
+    /// Asks Squid to shut down cleanly, so it flushes the access log and
+    /// removes its pid file, and kills it if that takes too long. The log
+    /// monitor is told to stop only once Squid has exited.
+    async fn stop(&mut self) -> Result<()> {
+        if self.child.try_wait()?.is_none() {
+            let signalled =
+                task::block_in_place(|| signal_squid(&self.binary, &self.config, "shutdown"));
+            if let Err(e) = signalled {
+                eprintln!("Warning: {e}; sending SIGTERM");
+                terminate(self.child.id());
             }
-            Err(e) => {
-                eprintln!("Warning: Could not check Squid process status: {e}");
-                println!("Proxy listening on 127.0.0.1:{PROXY_PORT}");
+            let deadline = Instant::now() + SQUID_SHUTDOWN_TIMEOUT;
+            while self.child.try_wait()?.is_none() {
+                if Instant::now() >= deadline {
+                    eprintln!(
+                        "Squid didn't exit within {}s; killing it",
+                        SQUID_SHUTDOWN_TIMEOUT.as_secs()
+                    );
+                    self.child.kill()?;
+                    self.child.wait()?;
+                    break;
+                }
+                tokio::time::sleep(Duration::from_millis(100)).await;
             }
         }
-
-        Ok(Self { child, running })
-    }
-
-    fn stop(&mut self) -> Result<()> {
         self.running.store(false, Ordering::SeqCst);
-        self.child.kill()?;
         Ok(())
     }
 }
 
+/// Kills Squid if it wasn't stopped, e.g. when the supervisor bails out on an
+/// error; `stop` is async, so this can't wait for a clean shutdown.
 impl Drop for SquidProcess {
     fn drop(&mut self) {
-        let _ = self.stop();
+        if matches!(self.child.try_wait(), Ok(None)) {
+            let _ = self.child.kill();
+            let _ = self.child.wait();
+        }
+        self.running.store(false, Ordering::SeqCst);
+    }
+}
+
+/// Shows a desktop notification where a notifier is available; best effort.
+fn notify_desktop(title: &str, body: &str) {
+    let result = if cfg!(target_os = "macos") {
+        let script = format!(
+            "display notification {:?} with title {:?}",
+            body.replace('"', "'"),
+            title
+        );
+        Command::new("osascript").arg("-e").arg(script).status()
+    } else {
+        Command::new("notify-send").arg(title).arg(body).status()
+    };
+    let _ = result;
+}
+
+/// Watches Squid and restarts it with exponential backoff when it dies,
+/// giving up (and shutting the proxy down) after `SQUID_MAX_RESTARTS`
+/// consecutive crashes. Owns the process; stops it when `stop` fires.
+async fn supervise_squid(
+    mut squid: SquidProcess,
+    state: Arc<control::DaemonState>,
+    mut stop: tokio::sync::oneshot::Receiver<()>,
+) -> Result<()> {
+    let mut crashes = 0u32;
+    loop {
+        tokio::select! {
+            _ = &mut stop => return squid.stop().await,
+            () = tokio::time::sleep(Duration::from_secs(1)) => {}
+        }
+        let Some(status) = squid.child.try_wait()? else {
+            if squid.started.elapsed() >= SQUID_STABLE_AFTER {
+                crashes = 0;
+            }
+            continue;
+        };
+
+        let reason = squid.failure_reason(status);
+        eprintln!("{reason}");
+        state.set_squid_pid(0);
+        state.record_squid_failure(reason.clone());
+        crashes += 1;
+        if crashes > SQUID_MAX_RESTARTS {
+            let message =
+                format!("Squid crashed {crashes} times in a row; giving up. Last error: {reason}");
+            eprintln!("{message}");
+            notify_desktop("digital-twin-proxy stopped", &message);
+            state.shutdown.notify_one();
+            return squid.stop().await;
+        }
+
+        let backoff = SQUID_RESTART_BACKOFF * 2u32.pow(crashes - 1);
+        notify_desktop(
+            "Squid crashed",
+            &format!("{reason}\nRestarting in {}s", backoff.as_secs()),
+        );
+        println!("Restarting Squid in {}s...", backoff.as_secs());
+        tokio::select! {
+            _ = &mut stop => return squid.stop().await,
+            () = tokio::time::sleep(backoff) => {}
+        }
+        match task::block_in_place(|| squid.restart()) {
+            Ok(()) => state.set_squid_pid(squid.pid()),
+            Err(e) => eprintln!("Failed to restart Squid: {e}"),
+        }
     }
 }
 
 // ------------ squid log parsing ------------------------------------------
-fn parse_squid_log_line(line: &str) -> Option<String> {
+struct AccessLine {
+    url: String,
+    tunnel: bool,
+    client: Option<String>,
+    user: Option<String>,
+    referer: Option<String>,
+    status: Option<u16>,
+    bytes: Option<u64>,
+}
+
+/// The HTTP status from a Squid `%Ss/%03>Hs` field such as `TCP_MISS/200`;
+/// Squid writes 000 when no response was received.
+fn squid_status(field: &str) -> Option<u16> {
+    field
+        .rsplit('/')
+        .next()?
+        .parse()
+        .ok()
+        .filter(|status| *status != 0)
+}
+
+fn parse_squid_log_line(line: &str) -> Option<AccessLine> {
     // Parse our custom log format:
-    // %ts.%03tu %6tr %>a %Ss/%03>Hs %<st %rm %ru %{Host}>h %un %Sh/%<a %mt
-    // Example: 1234567890.123   456 192.168.1.1 TCP_MISS/200 1234 GET http://example.com/ example.com - DIRECT/93.184.216.34 text/html
+    // %ts.%03tu %6tr %>a %Ss/%03>Hs %<st %rm %ru %{Host}>h %un %Sh/%<a %mt %{Referer}>h
+    // Example: 1234567890.123   456 192.168.1.1 TCP_MISS/200 1234 GET http://example.com/ example.com - DIRECT/93.184.216.34 text/html http://example.org/
 
     let parts: Vec<&str> = line.split_whitespace().collect();
     if parts.len() < 8 {
         return None;
     }
 
+    // parts[2] is the client address, parts[8] the proxy-auth username,
+    // parts[11] the Referer header (absent in logs from older configs)
+    let field = |i: usize| {
+        parts
+            .get(i)
+            .filter(|v| **v != "-")
+            .map(|v| (*v).to_string())
+    };
+    let client = field(2);
+    let user = field(8);
+    let referer = field(11);
+    let status = squid_status(parts[3]);
+    // parts[4] is the reply size (`%<st`)
+    let bytes = parts[4].parse().ok();
+
     // parts[6] is the request URL
     // parts[7] is the Host header
     let host = parts.get(7)?;
+    let tunnel = parts.get(5)? == &"CONNECT";
 
     // Determine protocol based on the URL
     let url = parts.get(6)?;
-    if url.starts_with("http://") || url.starts_with("https://") {
-        Some((*url).to_string())
-    } else if parts.get(5)? == &"CONNECT" {
+    let url = if url.starts_with("http://") || url.starts_with("https://") {
+        (*url).to_string()
+    } else if tunnel {
         // CONNECT method indicates HTTPS
-        Some(format!("https://{host}"))
+        format!("https://{host}")
     } else {
         // Default to HTTP
-        Some(format!("http://{host}"))
+        format!("http://{host}")
+    };
+
+    Some(AccessLine {
+        url,
+        tunnel,
+        client,
+        user,
+        referer,
+        status,
+        bytes,
+    })
+}
+
+/// Applies the domain rules to a parsed access-log line. In TLS interception
+/// mode the CONNECT line of a decrypted tunnel is dropped, since the full
+/// URLs of the requests inside it are logged separately.
+fn should_log(access: &AccessLine, rules: &DomainRules, proxy: ProxyArgs) -> bool {
+    let Some(host) = host_of(&access.url) else {
+        return true;
+    };
+    if !rules.captures(&host) {
+        return false;
     }
+    !(proxy.tls_intercept && access.tunnel && rules.bumps(&host))
 }
 
-async fn monitor_squid_logs(running: Arc<AtomicBool>) -> Result<()> {
+async fn monitor_squid_logs(
+    running: Arc<AtomicBool>,
+    proxy: ProxyArgs,
+    state: Arc<control::DaemonState>,
+) -> Result<()> {
+    let mut rules = DomainRules::load()?;
+    let mut taxonomy = Taxonomy::load()?;
     let mut last_position = 0u64;
 
     loop {
-        if !running.load(Ordering::SeqCst) {
-            break;
+        // Once Squid has exited, read what it wrote on the way out, then stop
+        let stopping = !running.load(Ordering::SeqCst);
+        state.expire_pause();
+        if state.take_reload() {
+            match DomainRules::load() {
+                Ok(reloaded) => rules = reloaded,
+                Err(e) => eprintln!("Keeping previous domain rules: {e}"),
+            }
+            match Taxonomy::load() {
+                Ok(reloaded) => taxonomy = reloaded,
+                Err(e) => eprintln!("Keeping previous categories: {e}"),
+            }
+            if let Err(e) = reconfigure_squid(proxy) {
+                eprintln!("Failed to reload Squid: {e}");
+            }
         }
 
         // Check if log file exists
@@ -339,10 +1142,28 @@ async fn monitor_squid_logs(running: Arc<AtomicBool>) -> Result<()> {
                 let mut reader = BufReader::new(file);
                 reader.seek_relative(i64::try_from(last_position).unwrap_or(i64::MAX))?;
 
+                // Lines written while paused are skipped for good, not deferred
+                let paused = state.is_paused();
                 for line in reader.lines().map_while(Result::ok) {
-                    if let Some(url) = parse_squid_log_line(&line) {
-                        if let Err(e) = append_log(&url) {
-                            eprintln!("Failed to log URL: {e}");
+                    if paused {
+                        continue;
+                    }
+                    if let Some(access) = parse_squid_log_line(&line) {
+                        if !should_log(&access, &rules, proxy) {
+                            continue;
+                        }
+                        let entry = LogEntry {
+                            client: access.client,
+                            user: access.user,
+                            referer: access.referer,
+                            status: access.status,
+                            bytes: access.bytes,
+                            category: taxonomy.categorize(&access.url).map(str::to_string),
+                            ..LogEntry::new(access.url, Utc::now())
+                        };
+                        match append_log(&entry) {
+                            Ok(()) => state.record_logged(),
+                            Err(e) => eprintln!("Failed to log URL: {e}"),
                         }
                     }
                 }
@@ -351,6 +1172,9 @@ async fn monitor_squid_logs(running: Arc<AtomicBool>) -> Result<()> {
             }
         }
 
+        if stopping {
+            break;
+        }
         tokio::time::sleep(Duration::from_millis(100)).await;
     }
 
@@ -365,48 +1189,104 @@ struct SummaryState {
 }
 
 impl SummaryState {
-    fn load() -> Self {
-        summary_path()
+    /// Loads the rolling summary for a persona (or the shared one for `None`).
+    fn load(persona: Option<&str>) -> Self {
+        summary_path(persona)
             .ok()
-            .and_then(|path| fs::read(path).ok())
-            .and_then(|data| serde_json::from_slice(&data).ok())
+            .and_then(|path| fs::read_to_string(path).ok())
+            .and_then(|text| serde_json::from_str(&crypto::open(&text).ok()?).ok())
             .unwrap_or_default()
     }
 
-    fn save(&self) -> Result<()> {
-        let path = summary_path()?;
+    fn save(&self, persona: Option<&str>) -> Result<()> {
+        let path = summary_path(persona)?;
         let tmp = path.with_extension("tmp");
-        fs::write(&tmp, serde_json::to_vec_pretty(self)?)?;
+        fs::write(&tmp, crypto::seal(serde_json::to_string_pretty(self)?)?)?;
         fs::rename(tmp, path)?;
-        Ok(())
+        append_summary_history(&SummaryRecord {
+            text: self.text.clone(),
+            updated: self.updated,
+            persona: persona.map(str::to_string),
+        })
     }
 }
 
-async fn fetch_page_content(url: &str) -> Result<String> {
+/// Every saved summary, kept so exports can show how the picture evolved.
+#[derive(Serialize, Deserialize)]
+struct SummaryRecord {
+    text: String,
+    updated: DateTime<Utc>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    persona: Option<String>,
+}
+
+fn append_summary_history(record: &SummaryRecord) -> Result<()> {
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(data_dir()?.join(SUMMARY_HISTORY_FILE))?;
+    writeln!(file, "{}", crypto::encode(record)?)?;
+    Ok(())
+}
+
+fn read_summary_history() -> Result<Vec<SummaryRecord>> {
+    crypto::require_key()?;
+    let file = match fs::File::open(data_dir()?.join(SUMMARY_HISTORY_FILE)) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+    Ok(BufReader::new(file)
+        .lines()
+        .map_while(Result::ok)
+        .filter_map(|line| crypto::decode(&line).ok())
+        .collect())
+}
+
+async fn fetch_page_content(url: &str, llm: &config::Llm) -> Result<String> {
     println!("Fetching content for url: {url}");
     let html = reqwest::get(url).await?.text().await?;
-    let document = Html::parse_document(&html);
-    let selector = Selector::parse("p").map_err(|_| anyhow::anyhow!("Failed to parse selector"))?;
-    let text = document
-        .select(&selector)
-        .map(|x| x.inner_html())
-        .collect::<Vec<_>>()
-        .join("\n");
+    // The parsed document isn't `Send`, so it must be gone before the next await
+    let (text, plain) = {
+        let document = Html::parse_document(&html);
+        let selector =
+            Selector::parse("p").map_err(|_| anyhow::anyhow!("Failed to parse selector"))?;
+        let text = document
+            .select(&selector)
+            .map(|x| x.inner_html())
+            .collect::<Vec<_>>()
+            .join("\n");
+        let plain = document
+            .select(&selector)
+            .map(|x| x.text().collect::<String>())
+            .collect::<Vec<_>>()
+            .join("\n");
+        (text, plain)
+    };
+    if let Err(e) = storage::cache_page(url, &plain) {
+        eprintln!("Failed to cache page content: {e}");
+    }
+    if let Err(e) = memory::index_page(llm, url, &plain).await {
+        eprintln!("Failed to remember page content: {e:#}");
+    }
     Ok(text)
 }
 
-fn create_system_prompt(previous: &str) -> String {
+fn create_system_prompt(previous: &str, categories: &str) -> String {
     format!("You are an intelligent browsing behavior analyst. Your task is to analyze web traffic patterns and provide meaningful insights.
 
 **Current Analysis:**
 {}\n
 **Instructions:**
 1. **Identify Patterns:** Look for recurring domains, workflows, or user behaviors
-2. **Categorize Activity:** Group URLs by purpose (work, research, entertainment, shopping, etc.)
+2. **Categorize Activity:** Start from the category counts below; only group pages counted as `other` by purpose yourself
 3. **Extract Insights:** What can you infer about the user's current tasks or interests?
 4. **Update Summary:** Merge new insights with existing analysis, prioritizing recent activity
 5. **Be Concise:** Provide a focused summary that highlights key patterns and changes
 
+**Activity by Category** (counted locally from the URLs in this batch; quote these numbers rather than estimating):
+{}
+
 **Tool Usage Guidelines:**
 You have a tool `fetch_page_content` to get deeper insights from specific pages. Use it strategically for:
 
@@ -427,6 +1307,13 @@ You have a tool `fetch_page_content` to get deeper insights from specific pages.
 - Social media feeds (unless showing specific new interests)
 - Shopping cart/checkout pages
 
+**History Tools:**
+Before calling something a new interest, check whether it is recurring:
+- `query_history`: how often a domain was visited before, and when
+- `search_memory`: passages from pages fetched in earlier analyses
+- `get_previous_summaries`: earlier summaries, to describe how interests changed over time
+- `get_page_title`: a page's title without its content; use it instead of `fetch_page_content` when the title is enough
+
 **Decision Criteria:**
 - Will this content help understand the user's current task or goal?
 - Does this URL suggest substantive, readable content?
@@ -439,17 +1326,17 @@ You have a tool `fetch_page_content` to get deeper insights from specific pages.
 - **Notable Changes:** How activity has evolved from the previous summary
 
 Provide your analysis:",
-        if previous.is_empty() { "None - this is the first analysis." } else { previous }
+        if previous.is_empty() { "None - this is the first analysis." } else { previous },
+        if categories.is_empty() { "None - no page views in this batch." } else { categories.trim_end() }
     )
 }
 
-fn create_fetch_tool() -> Result<Vec<ChatCompletionTool>> {
-    Ok(vec![ChatCompletionTool {
-        r#type: ChatCompletionToolType::Function,
-        function: FunctionObjectArgs::default()
-            .name("fetch_page_content")
-            .description("Fetches the content of a web page.")
-            .parameters(serde_json::json!({
+fn create_tools() -> Result<Vec<ChatCompletionTool>> {
+    Ok(vec![
+        tools::function(
+            "fetch_page_content",
+            "Fetches the content of a web page.",
+            serde_json::json!({
                 "type": "object",
                 "properties": {
                     "url": {
@@ -458,59 +1345,169 @@ fn create_fetch_tool() -> Result<Vec<ChatCompletionTool>> {
                     }
                 },
                 "required": ["url"]
-            }))
-            .build()?,
-    }])
+            }),
+        )?,
+        tools::function(
+            "search_memory",
+            "Finds passages from pages fetched in earlier analyses.",
+            serde_json::json!({
+                "type": "object",
+                "properties": {
+                    "query": {
+                        "type": "string",
+                        "description": "What to look for, e.g. a topic or question."
+                    }
+                },
+                "required": ["query"]
+            }),
+        )?,
+        tools::function(
+            "query_history",
+            "Shows how often a domain was visited before: request and day counts, first and last visit, most requested pages.",
+            serde_json::json!({
+                "type": "object",
+                "properties": {
+                    "host": {
+                        "type": "string",
+                        "description": "Domain to look up, e.g. docs.rs. Subdomains are included."
+                    },
+                    "since": {
+                        "type": "string",
+                        "description": "Optional start of the window: a duration like 30d or 12h, or an RFC 3339 time. Defaults to all history."
+                    }
+                },
+                "required": ["host"]
+            }),
+        )?,
+        tools::function(
+            "get_previous_summaries",
+            "Returns earlier saved summaries, newest first.",
+            serde_json::json!({
+                "type": "object",
+                "properties": {
+                    "n": {
+                        "type": "integer",
+                        "description": "How many summaries to return (1-10)."
+                    }
+                },
+                "required": ["n"]
+            }),
+        )?,
+        tools::function(
+            "get_page_title",
+            "Gets a page's title and content type without fetching its content.",
+            serde_json::json!({
+                "type": "object",
+                "properties": {
+                    "url": {
+                        "type": "string",
+                        "description": "The URL of the page."
+                    }
+                },
+                "required": ["url"]
+            }),
+        )?,
+    ])
+}
+
+async fn run_tool(
+    name: &str,
+    arguments: &str,
+    llm: &config::Llm,
+    persona: Option<&str>,
+) -> Result<String> {
+    let args: serde_json::Value = serde_json::from_str(arguments)?;
+    let arg = |key: &str| args.get(key).and_then(serde_json::Value::as_str);
+    let required = |key: &str| arg(key).ok_or_else(|| anyhow::anyhow!("missing argument `{key}`"));
+    match name {
+        "fetch_page_content" => fetch_page_content(required("url")?, llm).await,
+        "search_memory" => {
+            let found = memory::search(llm, required("query")?, MEMORY_RESULTS).await?;
+            if found.is_empty() {
+                Ok("No related pages in memory.".to_string())
+            } else {
+                Ok(memory::render(&found))
+            }
+        }
+        // The history tools read whole files, so keep them off the runtime's threads
+        "query_history" => {
+            let host = required("host")?.to_string();
+            let since = arg("since").map(str::to_string);
+            task::spawn_blocking(move || tools::query_history(&host, since.as_deref())).await?
+        }
+        "get_previous_summaries" => {
+            let n = args
+                .get("n")
+                .and_then(serde_json::Value::as_u64)
+                .unwrap_or(1);
+            let persona = persona.map(str::to_string);
+            task::spawn_blocking(move || {
+                tools::previous_summaries(
+                    persona.as_deref(),
+                    usize::try_from(n).unwrap_or(usize::MAX),
+                )
+            })
+            .await?
+        }
+        "get_page_title" => tools::page_title(required("url")?).await,
+        other => anyhow::bail!("unknown tool `{other}`"),
+    }
 }
 
 async fn handle_tool_calls(
     tool_calls: &[ChatCompletionMessageToolCall],
     messages: &mut Vec<ChatCompletionRequestMessage>,
+    llm: &config::Llm,
+    persona: Option<&str>,
 ) -> Result<()> {
     for tool_call in tool_calls {
-        let function_name = &tool_call.function.name;
-        if function_name == "fetch_page_content" {
-            let args: serde_json::Value = serde_json::from_str(&tool_call.function.arguments)?;
-            if let Some(url) = args.get("url").and_then(|u| u.as_str()) {
-                let content = fetch_page_content(url).await?;
-                messages.push(
-                    ChatCompletionRequestToolMessageArgs::default()
-                        .content(content)
-                        .tool_call_id(tool_call.id.clone())
-                        .build()?
-                        .into(),
-                );
+        let name = &tool_call.function.name;
+        // Every call needs an answer, or the follow-up request is rejected;
+        // failures are reported to the model so one dead link doesn't lose the summary
+        let content = match run_tool(name, &tool_call.function.arguments, llm, persona).await {
+            Ok(content) => content,
+            Err(e) => {
+                eprintln!("Tool {name} failed: {e:#}");
+                format!("{name} failed: {e:#}")
             }
-        }
+        };
+        messages.push(
+            ChatCompletionRequestToolMessageArgs::default()
+                .content(content)
+                .tool_call_id(tool_call.id.clone())
+                .build()?
+                .into(),
+        );
     }
     Ok(())
 }
 
 async fn summarize_with_llm(
+    persona: Option<&str>,
     previous: &str,
+    categories: &str,
     items: &[String],
-    model: &str,
-    api_base: &str,
-    api_key: Option<&str>,
+    llm: &config::Llm,
 ) -> Result<String> {
-    let mut config = OpenAIConfig::new().with_api_base(api_base);
-    if let Some(key) = api_key {
-        config = config.with_api_key(key);
-    }
-    let client = Client::with_config(config);
+    let llm = &llm.with_resolved_key().await?;
+    let model = &llm.model;
+    let client = llm.client().await?;
 
     let mut messages = vec![
         ChatCompletionRequestSystemMessageArgs::default()
-            .content(create_system_prompt(previous))
+            .content(create_system_prompt(previous, categories))
             .build()?
             .into(),
         ChatCompletionRequestUserMessageArgs::default()
-            .content(format!("**New Activity:**\n{}", items.join("\n")))
+            .content(format!(
+                "**New Activity** (browsing sessions, with estimated dwell time per page):\n{}",
+                items.join("\n\n")
+            ))
             .build()?
             .into(),
     ];
 
-    let tools = create_fetch_tool()?;
+    let tools = create_tools()?;
 
     let request = CreateChatCompletionRequestArgs::default()
         .model(model)
@@ -522,7 +1519,7 @@ async fn summarize_with_llm(
     let response = client.chat().create(request).await?;
 
     if let Some(tool_calls) = response.choices[0].message.tool_calls.as_ref() {
-        handle_tool_calls(tool_calls, &mut messages).await?;
+        handle_tool_calls(tool_calls, &mut messages, llm, persona).await?;
 
         let request = CreateChatCompletionRequestArgs::default()
             .model(model)
@@ -545,83 +1542,165 @@ async fn summarize_with_llm(
 }
 
 // ------------ ambient loop -------------------------------------------------
+/// A persona's entries whose summaries failed, retried with the next window.
+#[derive(Default)]
+struct Queued {
+    /// Ambient windows merged into `entries`
+    windows: usize,
+    entries: Vec<LogEntry>,
+}
+
 async fn ambient_loop(
     interval_secs: u64,
-    model: String,
-    api_base: String,
-    api_key: Option<String>,
+    max_items: usize,
+    llm: config::Llm,
+    rollup_depth: Option<usize>,
+    state: Arc<control::DaemonState>,
 ) -> Result<()> {
     let mut timer = tokio::time::interval(Duration::from_secs(interval_secs));
+    let mut window_start =
+        Utc::now() - CDuration::seconds(i64::try_from(interval_secs).unwrap_or(i64::MAX));
+    let mut queued: BTreeMap<Option<String>, Queued> = BTreeMap::new();
+    // Pause/resume markers from windows without traffic, so the next summary
+    // still learns about the gap
+    let mut markers: Vec<LogEntry> = Vec::new();
     loop {
-        timer.tick().await;
-        let cutoff =
-            Utc::now() - CDuration::seconds(i64::try_from(interval_secs).unwrap_or(i64::MAX));
-        let mut new_items = Vec::new();
-
-        let Ok(path) = log_path() else {
-            continue;
-        };
-        let Ok(file) = fs::File::open(path) else {
-            continue;
+        tokio::select! {
+            _ = timer.tick() => {},
+            () = state.summarize_now.notified() => timer.reset(),
+        }
+        let window_end = Utc::now();
+        let personas = Personas::load().unwrap_or_else(|e| {
+            eprintln!("Ignoring personas: {e}");
+            Personas::default()
+        });
+        let taxonomy = Taxonomy::load().unwrap_or_else(|e| {
+            eprintln!("Using built-in categories only: {e}");
+            Taxonomy::builtin()
+        });
+
+        // On a read error the window is retried on the next tick, queue intact
+        let entries = match read_log_since(Some(window_start)) {
+            Ok(entries) => entries,
+            Err(e) => {
+                eprintln!("log read error: {e}");
+                continue;
+            }
         };
-        for line in BufReader::new(file).lines().map_while(Result::ok) {
-            if let Ok(entry) = serde_json::from_str::<LogEntry>(&line) {
-                if entry.ts >= cutoff {
-                    new_items.push(entry.url);
-                }
+        window_start = window_end;
+        let mut new_items = std::mem::take(&mut queued);
+        for entry in entries.into_iter().filter(|e| e.ts < window_end) {
+            if entry.is_marker() {
+                markers.push(entry);
+                continue;
             }
+            let persona = personas.resolve(&entry).map(str::to_string);
+            new_items.entry(persona).or_default().entries.push(entry);
         }
+
         if new_items.is_empty() {
             continue;
         }
 
-        let mut state = SummaryState::load();
-        if state.text.is_empty() {
-            println!(
-                "Starting fresh AI analysis with {} new URLs...",
-                new_items.len()
-            );
-        } else {
-            println!(
-                "Updating existing analysis with {} new URLs (previous summary exists)",
-                new_items.len()
-            );
-        }
-        match summarize_with_llm(
-            &state.text,
-            &new_items,
-            &model,
-            &api_base,
-            api_key.as_deref(),
-        )
-        .await
-        {
-            Ok(summary) => {
-                state.text = summary;
-                state.updated = Utc::now();
-                if let Err(e) = state.save() {
-                    eprintln!("save error: {e}");
+        // Each persona keeps its own rolling summary
+        let window_markers = std::mem::take(&mut markers);
+        for (persona, mut item) in new_items {
+            item.windows += 1;
+            item.entries.extend(window_markers.iter().cloned());
+            let result = summarize_window(
+                persona.as_deref(),
+                &item.entries,
+                rollup_depth,
+                &taxonomy,
+                &llm,
+            )
+            .await;
+            if result.is_err() {
+                // Keep the retry bounded while the LLM stays down
+                item.entries.sort_by_key(|e| e.ts);
+                let excess = item.entries.len().saturating_sub(max_items);
+                if excess > 0 {
+                    item.entries.drain(..excess);
+                    state.record_queued_dropped(excess);
                 }
+                queued.insert(persona, item);
             }
-            Err(e) => eprintln!("summarization error: {e}"),
+            state.record_summary(result);
+        }
+        state.set_queued_windows(queued.values().map(|q| q.windows).max().unwrap_or(0));
+    }
+}
+
+async fn summarize_window(
+    persona: Option<&str>,
+    entries: &[LogEntry],
+    rollup_depth: Option<usize>,
+    taxonomy: &Taxonomy,
+    llm: &config::Llm,
+) -> Result<(), String> {
+    let label = persona.map_or_else(String::new, |p| format!(" [{p}]"));
+    let sessions = session::render_sessions(&session::sessionize(entries, rollup_depth), entries);
+    let categories = taxonomy.render_counts(entries);
+    let mut state = SummaryState::load(persona);
+    if state.text.is_empty() {
+        println!(
+            "Starting fresh AI analysis{label} with {} new URLs...",
+            entries.len()
+        );
+    } else {
+        println!(
+            "Updating existing analysis{label} with {} new URLs (previous summary exists)",
+            entries.len()
+        );
+    }
+    match summarize_with_llm(persona, &state.text, &categories, &sessions, llm).await {
+        Ok(summary) => {
+            state.text = summary;
+            state.updated = Utc::now();
+            if let Err(e) = state.save(persona) {
+                eprintln!("save error: {e}");
+            }
+            Ok(())
+        }
+        Err(e) => {
+            eprintln!("summarization error: {e}");
+            Err(e.to_string())
         }
     }
 }
 
 // ------------ commands -----------------------------------------------------
-fn run_log() -> Result<()> {
+fn run_log(proxy: ProxyArgs) -> Result<()> {
+    crypto::require_key()?;
     let rt = Runtime::new()?;
     rt.block_on(async {
-        let mut squid = SquidProcess::start()?;
+        let squid = SquidProcess::start(proxy)?;
         let running = Arc::clone(&squid.running);
+        let state = Arc::new(control::DaemonState::new(false));
+        state.set_squid_pid(squid.pid());
+        let supervisor = Supervisor::spawn(squid, Arc::clone(&state));
+
+        let log_monitor = task::spawn(monitor_squid_logs(
+            Arc::clone(&running),
+            proxy,
+            Arc::clone(&state),
+        ));
+        let pac_server = spawn_pac_server(proxy);
+        let control_server = control::spawn(Arc::clone(&state));
 
-        let log_monitor = task::spawn(monitor_squid_logs(Arc::clone(&running)));
-
-        signal::ctrl_c().await?;
+        tokio::select! {
+            result = shutdown_signal() => result?,
+            () = state.shutdown.notified() => {},
+        }
         println!("\nShutting down proxy...");
 
-        squid.stop()?;
-        log_monitor.abort();
+        supervisor.stop().await?;
+        drain_monitor(log_monitor).await;
+        if let Some(pac_server) = pac_server {
+            pac_server.abort();
+        }
+        control_server.abort();
+        control::cleanup();
 
         Ok(())
     })
@@ -629,54 +1708,77 @@ fn run_log() -> Result<()> {
 
 fn run_analyze(
     since_str: &str,
+    client: Option<&str>,
     max_items: usize,
-    model: &str,
-    api_base: &str,
-    api_key: Option<&String>,
+    llm: &config::Llm,
+    rollup_depth: Option<usize>,
 ) -> Result<()> {
     println!("Starting analysis for period: {since_str}");
     let start = parse_since(since_str)?;
     println!("Parsed start time: {start}");
+    crypto::require_key()?;
+    let personas = Personas::load()?;
+    // A persona's summary covers all of its members, so one member stands for all
+    let client = match client.map(|c| (c, personas.persona_for(c))) {
+        Some((c, Some(persona))) if c != persona => {
+            println!("{c} belongs to persona {persona}; analyzing all of its traffic");
+            Some(persona)
+        }
+        _ => client,
+    };
+    let persona = client.map(|c| personas.summary_key(c)).transpose()?;
     let mut items = Vec::new();
-    let Ok(path) = log_path() else {
+    let files = storage::log_files_since(Some(start))?;
+    if files.is_empty() {
         println!("No log file found");
         return Ok(());
-    };
-    println!("Opening log file: {}", path.display());
-    let Ok(file) = fs::File::open(path) else {
-        println!("Could not open log file");
-        return Ok(());
-    };
-    println!("Reading log file...");
-    for line in BufReader::new(file).lines().map_while(Result::ok) {
-        match serde_json::from_str::<LogEntry>(&line) {
-            Ok(entry) => {
-                if entry.ts >= start {
-                    items.push(entry.url);
-                }
-            }
-            Err(e) => {
-                eprintln!("Warning: Failed to parse log line: {line} (error: {e})");
+    }
+    let mut skipped = 0;
+    'files: for path in files {
+        println!("Reading log file: {}", path.display());
+        let Ok(file) = fs::File::open(&path) else {
+            println!("Could not open log file");
+            continue;
+        };
+        for line in BufReader::new(file).lines().map_while(Result::ok) {
+            let Ok(entry) = crypto::decode::<LogEntry>(&line) else {
+                skipped += 1;
                 continue;
+            };
+            // Pause markers apply to everyone sharing the proxy; without a
+            // filter the shared summary only covers clients with no persona
+            let wanted = entry.is_marker()
+                || client.map_or_else(
+                    || personas.resolve(&entry).is_none(),
+                    |c| personas.matches(c, &entry),
+                );
+            if entry.ts >= start && wanted {
+                items.push(entry);
+            }
+            if items.len() >= max_items {
+                break 'files;
             }
         }
-        if items.len() >= max_items {
-            break;
-        }
     }
-    if items.is_empty() {
+    if skipped > 0 {
+        eprintln!("Warning: Skipped {skipped} unparseable log lines");
+    }
+    let urls = items.iter().filter(|e| !e.is_marker()).count();
+    if urls == 0 {
         println!("No traffic since {start}");
         return Ok(());
     }
 
+    let session_list = session::sessionize(&items, rollup_depth);
+    let sessions = session::render_sessions(&session_list, &items);
     println!(
-        "Found {} URLs to analyze. Starting AI analysis with {}...",
-        items.len(),
-        model
+        "Found {urls} URLs in {} sessions to analyze. Starting AI analysis with {}...",
+        session_list.len(),
+        llm.model
     );
 
     // Check for existing summary
-    let state = SummaryState::load();
+    let state = SummaryState::load(persona.as_deref());
     if state.text.is_empty() {
         println!("Previous analysis: None - this is a fresh analysis");
     } else {
@@ -687,12 +1789,13 @@ fn run_analyze(
     }
 
     let rt = Runtime::new().context("Failed to create tokio runtime")?;
+    let categories = Taxonomy::load()?.render_counts(&items);
     let summary = rt.block_on(summarize_with_llm(
+        persona.as_deref(),
         &state.text,
-        &items,
-        model,
-        api_base,
-        api_key.map(std::string::String::as_str),
+        &categories,
+        &sessions,
+        llm,
     ))?;
 
     // Save the updated summary
@@ -700,7 +1803,7 @@ fn run_analyze(
         text: summary.clone(),
         updated: Utc::now(),
     };
-    if let Err(e) = updated_state.save() {
+    if let Err(e) = updated_state.save(persona.as_deref()) {
         eprintln!("Warning: Failed to save updated summary: {e}");
     }
 
@@ -710,47 +1813,196 @@ fn run_analyze(
 
 fn run_ambient(
     interval_secs: u64,
-    model: &str,
-    api_base: &str,
-    api_key: Option<&String>,
+    max_items: usize,
+    llm: config::Llm,
+    rollup_depth: Option<usize>,
+    proxy: ProxyArgs,
 ) -> Result<()> {
+    crypto::require_key()?;
     let rt = Runtime::new().context("Failed to create tokio runtime")?;
-    let model = model.to_owned();
-    let api_base = api_base.to_owned();
-    let api_key = api_key.map(std::borrow::ToOwned::to_owned);
     rt.block_on(async {
-        let mut squid = SquidProcess::start()?;
+        let squid = SquidProcess::start(proxy)?;
         let running = Arc::clone(&squid.running);
+        let state = Arc::new(control::DaemonState::new(true));
+        state.set_squid_pid(squid.pid());
+        let supervisor = Supervisor::spawn(squid, Arc::clone(&state));
+
+        let mut log_monitor = task::spawn(monitor_squid_logs(
+            Arc::clone(&running),
+            proxy,
+            Arc::clone(&state),
+        ));
+        let ambient = task::spawn(ambient_loop(
+            interval_secs,
+            max_items,
+            llm,
+            rollup_depth,
+            Arc::clone(&state),
+        ));
+        let pac_server = spawn_pac_server(proxy);
+        let control_server = control::spawn(Arc::clone(&state));
+
+        let monitor_exited = tokio::select! {
+            _ = shutdown_signal() => false,
+            () = state.shutdown.notified() => false,
+            _ = &mut log_monitor => true,
+            _ = ambient => false,
+        };
+        println!("\nShutting down proxy...");
 
-        let log_monitor = task::spawn(monitor_squid_logs(Arc::clone(&running)));
-        let ambient = task::spawn(ambient_loop(interval_secs, model, api_base, api_key));
+        supervisor.stop().await?;
+        if !monitor_exited {
+            drain_monitor(log_monitor).await;
+        }
+        if let Some(pac_server) = pac_server {
+            pac_server.abort();
+        }
+        control_server.abort();
+        control::cleanup();
+        Ok(())
+    })
+}
+
+/// Handle to the `supervise_squid` task.
+struct Supervisor {
+    stop: tokio::sync::oneshot::Sender<()>,
+    task: task::JoinHandle<Result<()>>,
+}
+
+impl Supervisor {
+    fn spawn(squid: SquidProcess, state: Arc<control::DaemonState>) -> Self {
+        let (stop, stop_rx) = tokio::sync::oneshot::channel();
+        Self {
+            stop,
+            task: task::spawn(supervise_squid(squid, state, stop_rx)),
+        }
+    }
+
+    /// Stops Squid (unless the supervisor already gave up) and waits for it.
+    async fn stop(self) -> Result<()> {
+        let _ = self.stop.send(());
+        self.task.await?
+    }
+}
 
+/// Resolves on Ctrl-C, or on SIGTERM from a service manager.
+async fn shutdown_signal() -> Result<()> {
+    #[cfg(unix)]
+    {
+        let mut terminate = signal::unix::signal(signal::unix::SignalKind::terminate())?;
         tokio::select! {
-            _ = signal::ctrl_c() => {
-                println!("\nShutting down proxy...");
-            },
-            _ = log_monitor => {},
-            _ = ambient => {},
+            result = signal::ctrl_c() => result?,
+            _ = terminate.recv() => {},
         }
+    }
+    #[cfg(not(unix))]
+    signal::ctrl_c().await?;
+    Ok(())
+}
 
-        squid.stop()?;
-        Ok(())
+/// Waits for the log monitor's final pass over the access log.
+async fn drain_monitor(monitor: task::JoinHandle<Result<()>>) {
+    match tokio::time::timeout(Duration::from_secs(5), monitor).await {
+        Ok(Ok(Err(e))) => eprintln!("Log monitor error: {e}"),
+        Ok(Err(e)) => eprintln!("Log monitor failed: {e}"),
+        Err(_) => eprintln!("Log monitor didn't finish draining the access log"),
+        Ok(Ok(Ok(()))) => {}
+    }
+}
+
+fn spawn_pac_server(proxy: ProxyArgs) -> Option<task::JoinHandle<()>> {
+    let port = proxy.pac_port?;
+    Some(task::spawn(async move {
+        if let Err(e) = pac::serve(port, "127.0.0.1".to_string()).await {
+            eprintln!("PAC server error: {e}");
+        }
+    }))
+}
+
+fn run_control(request: &control::Request) -> Result<()> {
+    println!("{}", control::send(request)?);
+    Ok(())
+}
+
+/// Runs without loading settings first, since `config` is how a broken
+/// `config.toml` gets repaired.
+fn run_config(profile: Option<&str>, command: &ConfigCommand) -> Result<()> {
+    match *command {
+        ConfigCommand::Show => match config::Settings::load(profile) {
+            Ok(settings) => {
+                settings.show();
+                Ok(())
+            }
+            Err(e) => {
+                config::Settings::without_file()?.show();
+                Err(e.context("Fix the file, or replace it with `config init --force`"))
+            }
+        },
+        ConfigCommand::Init { force } => config::run_init(force),
+    }
+}
+
+fn run_pause(duration: Option<&str>) -> Result<()> {
+    let until = duration
+        .map(|d| parse_duration(d).map(|d| Utc::now() + d))
+        .transpose()?;
+    run_control(&control::Request::Pause { until })
+}
+
+fn run_pac_show(proxy_host: &str) -> Result<()> {
+    print!("{}", pac::render_pac(&DomainRules::load()?, proxy_host));
+    Ok(())
+}
+
+fn run_pac_serve(port: u16, proxy_host: String) -> Result<()> {
+    let rt = Runtime::new().context("Failed to create tokio runtime")?;
+    rt.block_on(async {
+        tokio::select! {
+            result = pac::serve(port, proxy_host) => result,
+            _ = signal::ctrl_c() => Ok(()),
+        }
     })
 }
 
-// ------------ since parser -------------------------------------------------
-fn parse_since(input: &str) -> Result<DateTime<Utc>> {
-    if let Some(num) = input.strip_suffix('d') {
-        let n: i64 = num.parse()?;
-        return Ok(Utc::now() - CDuration::days(n));
+fn run_squid_check(proxy: ProxyArgs) -> Result<()> {
+    let squid_binary = find_squid_binary().ok_or_else(|| {
+        print_install_instructions();
+        anyhow::anyhow!("Squid is not installed")
+    })?;
+    let config_path = squid_config_path(proxy).context("Failed to setup squid configuration")?;
+    let overlay_path = squid_overlay_path()?;
+
+    println!("Config:  {}", config_path.display());
+    if overlay_path.exists() {
+        println!("Overlay: {}", overlay_path.display());
+    } else {
+        println!("Overlay: {} (not present)", overlay_path.display());
     }
-    if let Some(num) = input.strip_suffix('h') {
-        let n: i64 = num.parse()?;
-        return Ok(Utc::now() - CDuration::hours(n));
+
+    check_squid_config(&squid_binary, &config_path)?;
+    println!("Squid configuration OK");
+    Ok(())
+}
+
+// ------------ since parser -------------------------------------------------
+/// Parses a `<n>d`, `<n>h` or `<n>m` duration.
+fn parse_duration(input: &str) -> Result<CDuration> {
+    let invalid = || anyhow::anyhow!("Invalid duration {input:?} (expected e.g. 30m, 2h or 7d)");
+    let unit = input.chars().last().ok_or_else(invalid)?;
+    let n: i64 = input[..input.len() - unit.len_utf8()]
+        .parse()
+        .map_err(|_| invalid())?;
+    match unit {
+        'd' => Ok(CDuration::days(n)),
+        'h' => Ok(CDuration::hours(n)),
+        'm' => Ok(CDuration::minutes(n)),
+        _ => Err(invalid()),
     }
-    if let Some(num) = input.strip_suffix('m') {
-        let n: i64 = num.parse()?;
-        return Ok(Utc::now() - CDuration::minutes(n));
+}
+
+fn parse_since(input: &str) -> Result<DateTime<Utc>> {
+    if input.ends_with(['d', 'h', 'm']) {
+        return Ok(Utc::now() - parse_duration(input)?);
     }
     DateTime::parse_from_rfc3339(input)
         .map(|dt| dt.with_timezone(&Utc))
@@ -761,20 +2013,99 @@ fn parse_since(input: &str) -> Result<DateTime<Utc>> {
 fn main() -> Result<()> {
     dotenv().ok();
     let cli = Cli::parse();
+    if let Commands::Config { command } = &cli.command {
+        return run_config(cli.profile.as_deref(), command);
+    }
+    let settings = config::Settings::load(cli.profile.as_deref())?;
+    let key_file = settings.get(&config::ENCRYPTION_KEY_FILE, None)?;
+    crypto::init(&cli.keys.or_key_file(key_file))?;
     match cli.command {
-        Commands::Log => run_log(),
+        Commands::Log { proxy } => run_log(proxy.resolve(&settings)?),
         Commands::Analyze {
             since,
+            client,
             max_items,
-            model,
-            api_base,
-            api_key,
-        } => run_analyze(&since, max_items, &model, &api_base, api_key.as_ref()),
+            llm,
+            rollup_depth,
+        } => run_analyze(
+            &since,
+            client.as_deref(),
+            settings.value(&config::MAX_ITEMS, max_items)?,
+            &settings.llm(&llm)?,
+            settings.get(&config::ROLLUP_DEPTH, rollup_depth)?,
+        ),
         Commands::Ambient {
             interval,
-            model,
-            api_base,
-            api_key,
-        } => run_ambient(interval, &model, &api_base, api_key.as_ref()),
+            max_items,
+            llm,
+            rollup_depth,
+            proxy,
+        } => run_ambient(
+            settings.value(&config::INTERVAL, interval)?,
+            settings.value(&config::MAX_ITEMS, max_items)?,
+            settings.llm(&llm)?,
+            settings.get(&config::ROLLUP_DEPTH, rollup_depth)?,
+            proxy.resolve(&settings)?,
+        ),
+        Commands::Squid { command } => match command {
+            SquidCommand::Check { proxy } => run_squid_check(proxy.resolve(&settings)?),
+        },
+        Commands::Ca { command } => match command {
+            CaCommand::Show => tls::run_ca_show(),
+            CaCommand::Regenerate => tls::run_ca_regenerate(),
+        },
+        Commands::Import {
+            path,
+            format,
+            base_url,
+            since,
+        } => {
+            let since = since.as_deref().map(parse_since).transpose()?;
+            import::run_import(path.as_deref(), format, base_url.as_deref(), since)
+        }
+        Commands::Export {
+            format,
+            since,
+            until,
+            out,
+        } => export::run_export(
+            format,
+            since.as_deref().map(parse_since).transpose()?,
+            until.as_deref().map(parse_since).transpose()?,
+            out.as_deref(),
+        ),
+        Commands::Search(args) => search::run_search(&args),
+        Commands::Stats(args) => stats::run_stats(&args),
+        Commands::Recall(args) => memory::run_recall(&args, &settings.embedding_llm(&args.llm)?),
+        Commands::Gc {
+            keep_raw_days,
+            dry_run,
+        } => storage::run_gc(
+            settings.value(&config::KEEP_RAW_DAYS, keep_raw_days)?,
+            dry_run,
+        ),
+        Commands::Rekey(args) => crypto::run_rekey(&args),
+        Commands::Pause { duration } => run_pause(duration.as_deref()),
+        Commands::Resume => run_control(&control::Request::Resume),
+        Commands::Status => run_control(&control::Request::Status),
+        Commands::SummarizeNow => run_control(&control::Request::SummarizeNow),
+        Commands::ReloadConfig => run_control(&control::Request::ReloadConfig),
+        Commands::Stop => run_control(&control::Request::Stop),
+        Commands::Service { command } => match command {
+            ServiceCommand::Install => service::run_install(&settings),
+            ServiceCommand::Uninstall => service::run_uninstall(),
+            ServiceCommand::Status => service::run_status(),
+        },
+        Commands::Doctor { llm } => {
+            doctor::run_doctor(settings.llm(&llm), ProxyArgs::default().resolve(&settings)?)
+        }
+        Commands::Login { api_base, delete } => {
+            secrets::run_login(&settings.api_base(api_base)?, delete)
+        }
+        Commands::Config { .. } => unreachable!("config commands run before settings load"),
+        Commands::Pac { command } => match command {
+            PacCommand::Show { proxy_host } => run_pac_show(&proxy_host),
+            PacCommand::Serve { port, proxy_host } => run_pac_serve(port, proxy_host),
+        },
     }
 }
@@ -0,0 +1,401 @@
+use anyhow::{Context, Result};
+use async_openai::types::CreateEmbeddingRequestArgs;
+use chrono::{DateTime, Local, Utc};
+use clap::Args;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fmt::Write as _,
+    fs,
+    io::{BufRead, BufReader, Write},
+};
+use tokio::runtime::Runtime;
+
+use crate::{
+    config::{Llm, LlmArgs},
+    crypto, data_dir,
+    storage::{lock_appends, write_atomic},
+};
+
+// ------------ semantic memory ----------------------------------------------
+const MEMORY_FILE: &str = "memory.ndjson";
+/// `EMBEDDING_MODEL` value that selects the built-in hash embeddings.
+pub const HASH_MODEL: &str = "hash";
+const HASH_DIMS: usize = 256;
+/// Target chunk length in characters.
+const CHUNK_CHARS: usize = 1000;
+/// Chunks embedded per page, so a huge page can't blow up the request.
+const MAX_CHUNKS: usize = 24;
+const SNIPPET_CHARS: usize = 300;
+
+/// One embedded passage of a fetched page.
+#[derive(Serialize, Deserialize)]
+struct Chunk {
+    url: String,
+    fetched: DateTime<Utc>,
+    /// Embedding model that produced `vector`; other models' vectors aren't comparable
+    model: String,
+    text: String,
+    vector: Vec<f32>,
+}
+
+/// The best matching passage of a remembered page.
+#[derive(Serialize)]
+pub struct Recollection {
+    pub url: String,
+    pub fetched: DateTime<Utc>,
+    pub score: f32,
+    pub text: String,
+}
+
+/// Splits text into passages of about `CHUNK_CHARS`, breaking at paragraph
+/// and then word boundaries.
+fn chunk_text(text: &str) -> Vec<String> {
+    let mut chunks = Vec::new();
+    let mut current = String::new();
+    for word in text
+        .lines()
+        .flat_map(|line| line.split_whitespace().chain(std::iter::once("\n")))
+    {
+        if word == "\n" {
+            if current.len() >= CHUNK_CHARS / 2 {
+                chunks.push(std::mem::take(&mut current));
+            }
+            continue;
+        }
+        if current.len() + word.len() >= CHUNK_CHARS && !current.is_empty() {
+            chunks.push(std::mem::take(&mut current));
+        }
+        if !current.is_empty() {
+            current.push(' ');
+        }
+        current.push_str(word);
+    }
+    if !current.is_empty() {
+        chunks.push(current);
+    }
+    chunks.truncate(MAX_CHUNKS);
+    chunks
+}
+
+/// FNV-1a, which unlike `DefaultHasher` is stable across Rust releases.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &b| {
+        (hash ^ u64::from(b)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// Deterministic bag-of-words embedding: each lowercase word is hashed to a
+/// signed dimension. Needs no endpoint, but only matches shared words.
+fn hash_embedding(text: &str) -> Vec<f32> {
+    let mut vector = vec![0.0; HASH_DIMS];
+    for word in text
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+    {
+        let hash = fnv1a(word.to_lowercase().as_bytes());
+        let sign = if hash >> 63 == 0 { 1.0 } else { -1.0 };
+        vector[(hash % HASH_DIMS as u64) as usize] += sign;
+    }
+    vector
+}
+
+fn cosine(a: &[f32], b: &[f32]) -> f32 {
+    let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let norm = |v: &[f32]| v.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let denominator = norm(a) * norm(b);
+    if denominator == 0.0 {
+        0.0
+    } else {
+        dot / denominator
+    }
+}
+
+async fn embed(llm: &Llm, inputs: Vec<String>) -> Result<Vec<Vec<f32>>> {
+    if llm.embedding_model == HASH_MODEL {
+        return Ok(inputs.iter().map(|text| hash_embedding(text)).collect());
+    }
+    let count = inputs.len();
+    let request = CreateEmbeddingRequestArgs::default()
+        .model(&llm.embedding_model)
+        .input(inputs)
+        .build()?;
+    let mut response = llm
+        .client()
+        .await?
+        .embeddings()
+        .create(request)
+        .await
+        .with_context(|| format!("Embedding with {} failed", llm.embedding_model))?;
+    if response.data.len() != count {
+        anyhow::bail!(
+            "{} returned {} embeddings for {count} inputs",
+            llm.embedding_model,
+            response.data.len()
+        );
+    }
+    response.data.sort_by_key(|e| e.index);
+    Ok(response.data.into_iter().map(|e| e.embedding).collect())
+}
+
+/// Chunks and embeds the text fetched for `url` and appends it to the index.
+pub async fn index_page(llm: &Llm, url: &str, text: &str) -> Result<()> {
+    let chunks = chunk_text(text);
+    if chunks.is_empty() {
+        return Ok(());
+    }
+    let vectors = embed(llm, chunks.clone()).await?;
+    let fetched = Utc::now();
+    let mut out = String::new();
+    for (text, vector) in chunks.into_iter().zip(vectors) {
+        let chunk = Chunk {
+            url: url.to_string(),
+            fetched,
+            model: llm.embedding_model.clone(),
+            text,
+            vector,
+        };
+        out.push_str(&crypto::encode(&chunk)?);
+        out.push('\n');
+    }
+    // `gc` may be replacing the file
+    let _lock = lock_appends()?;
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(data_dir()?.join(MEMORY_FILE))?;
+    file.write_all(out.as_bytes())?;
+    Ok(())
+}
+
+/// The lines of the latest fetch of each page per embedding model, in their
+/// original order, and how many older ones were left out. Lines that don't
+/// parse are kept.
+fn latest_fetches(text: &str) -> (Vec<&str>, usize) {
+    let lines: Vec<(&str, Option<Chunk>)> = text
+        .lines()
+        .filter(|l| !l.trim().is_empty())
+        .map(|line| (line, crypto::decode::<Chunk>(line).ok()))
+        .collect();
+    let mut latest: HashMap<(&str, &str), DateTime<Utc>> = HashMap::new();
+    for chunk in lines.iter().filter_map(|(_, chunk)| chunk.as_ref()) {
+        let fetched = latest
+            .entry((&chunk.url, &chunk.model))
+            .or_insert(chunk.fetched);
+        *fetched = (*fetched).max(chunk.fetched);
+    }
+    let mut dropped = 0;
+    let mut kept = Vec::new();
+    for (line, chunk) in &lines {
+        match chunk {
+            Some(c) if latest.get(&(c.url.as_str(), c.model.as_str())) != Some(&c.fetched) => {
+                dropped += 1;
+            }
+            _ => kept.push(*line),
+        }
+    }
+    (kept, dropped)
+}
+
+/// Drops passages of older fetches from the index, like `gc` does for the
+/// page cache.
+pub fn compact() -> Result<()> {
+    let path = data_dir()?.join(MEMORY_FILE);
+    let _lock = lock_appends()?;
+    let text = match fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+    };
+    let (kept, dropped) = latest_fetches(&text);
+    if dropped == 0 {
+        return Ok(());
+    }
+    let mut data = String::new();
+    for line in kept {
+        data.push_str(line);
+        data.push('\n');
+    }
+    write_atomic(&path, data.as_bytes())?;
+    println!("Dropped {dropped} remembered passages from older fetches");
+    Ok(())
+}
+
+fn read_chunks(model: &str) -> Result<Vec<Chunk>> {
+    let Ok(file) = fs::File::open(data_dir()?.join(MEMORY_FILE)) else {
+        return Ok(Vec::new());
+    };
+    Ok(BufReader::new(file)
+        .lines()
+        .map_while(Result::ok)
+        .filter_map(|line| crypto::decode::<Chunk>(&line).ok())
+        .filter(|chunk| chunk.model == model)
+        .collect())
+}
+
+/// The best passage per page, best pages first. Only the latest fetch of
+/// each page counts, so pages that changed aren't remembered twice.
+fn rank(chunks: Vec<Chunk>, query: &[f32], limit: usize) -> Vec<Recollection> {
+    let mut latest: HashMap<String, DateTime<Utc>> = HashMap::new();
+    for chunk in &chunks {
+        let fetched = latest.entry(chunk.url.clone()).or_insert(chunk.fetched);
+        *fetched = (*fetched).max(chunk.fetched);
+    }
+    let mut best: HashMap<String, Recollection> = HashMap::new();
+    for chunk in chunks {
+        if latest.get(&chunk.url) != Some(&chunk.fetched) || chunk.vector.len() != query.len() {
+            continue;
+        }
+        let score = cosine(&chunk.vector, query);
+        if score <= 0.0 || best.get(&chunk.url).is_some_and(|b| b.score >= score) {
+            continue;
+        }
+        best.insert(
+            chunk.url.clone(),
+            Recollection {
+                url: chunk.url,
+                fetched: chunk.fetched,
+                score,
+                text: chunk.text,
+            },
+        );
+    }
+    let mut found: Vec<Recollection> = best.into_values().collect();
+    found.sort_by(|a, b| b.score.total_cmp(&a.score).then_with(|| a.url.cmp(&b.url)));
+    found.truncate(limit);
+    found
+}
+
+/// Pages fetched in earlier analyses that are most relevant to `query`.
+pub async fn search(llm: &Llm, query: &str, limit: usize) -> Result<Vec<Recollection>> {
+    crypto::require_key()?;
+    let chunks = read_chunks(&llm.embedding_model)?;
+    if chunks.is_empty() {
+        return Ok(Vec::new());
+    }
+    let query = embed(llm, vec![query.to_string()])
+        .await?
+        .pop()
+        .unwrap_or_default();
+    Ok(rank(chunks, &query, limit))
+}
+
+/// Renders recollections for the terminal and for the `search_memory` tool.
+pub fn render(found: &[Recollection]) -> String {
+    let mut out = String::new();
+    for r in found {
+        let mut snippet: String = r.text.chars().take(SNIPPET_CHARS).collect();
+        if snippet.len() < r.text.len() {
+            snippet.push('…');
+        }
+        let _ = writeln!(
+            out,
+            "{:.2}  {}  (fetched {})\n      {snippet}\n",
+            r.score,
+            r.url,
+            r.fetched.with_timezone(&Local).format("%Y-%m-%d %H:%M")
+        );
+    }
+    out
+}
+
+#[derive(Args)]
+pub struct RecallArgs {
+    question: String,
+    /// Pages to show
+    #[arg(short = 'n', long, default_value_t = 5)]
+    limit: usize,
+    /// Print matches as JSON
+    #[arg(long)]
+    json: bool,
+    #[command(flatten)]
+    pub llm: LlmArgs,
+}
+
+pub fn run_recall(args: &RecallArgs, llm: &Llm) -> Result<()> {
+    let rt = Runtime::new().context("Failed to create tokio runtime")?;
+    let found = rt.block_on(search(llm, &args.question, args.limit))?;
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&found)?);
+    } else if found.is_empty() {
+        println!(
+            "Nothing related in memory (pages are remembered when `analyze` or `ambient` \
+             fetch them with {})",
+            llm.embedding_model
+        );
+    } else {
+        print!("{}", render(&found));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(url: &str, text: &str, minutes: i64) -> Chunk {
+        Chunk {
+            url: url.to_string(),
+            fetched: DateTime::from_timestamp(1_700_000_000 + minutes * 60, 0).unwrap_or_default(),
+            model: HASH_MODEL.to_string(),
+            text: text.to_string(),
+            vector: hash_embedding(text),
+        }
+    }
+
+    #[test]
+    fn hash_embedding_is_deterministic_and_case_insensitive() {
+        assert_eq!(
+            hash_embedding("Tokio runtime"),
+            hash_embedding("tokio RUNTIME")
+        );
+        assert_eq!(hash_embedding("").len(), HASH_DIMS);
+        assert!(cosine(&hash_embedding("rust async"), &hash_embedding("rust async")) > 0.99);
+    }
+
+    #[test]
+    fn chunks_stay_under_the_limit_and_keep_every_word() {
+        let text = "word ".repeat(CHUNK_CHARS);
+        let chunks = chunk_text(&text);
+        assert!(chunks.len() > 1);
+        assert!(chunks.iter().all(|c| c.len() <= CHUNK_CHARS));
+        let words: usize = chunks.iter().map(|c| c.split_whitespace().count()).sum();
+        assert_eq!(words, CHUNK_CHARS);
+        assert!(chunk_text(" \n ").is_empty());
+    }
+
+    #[test]
+    fn latest_fetches_keeps_each_model_and_unparsed_lines() -> Result<()> {
+        let mut other_model = chunk("https://a.example/", "old text", 0);
+        other_model.model = "text-embedding-3-small".to_string();
+        let lines = [
+            crypto::encode(&chunk("https://a.example/", "old text", 0))?,
+            crypto::encode(&other_model)?,
+            "not json".to_string(),
+            crypto::encode(&chunk("https://a.example/", "new text", 5))?,
+            crypto::encode(&chunk("https://a.example/", "more new text", 5))?,
+            crypto::encode(&chunk("https://b.example/", "other page", 1))?,
+        ];
+        let text = lines.join("\n");
+        let (kept, dropped) = latest_fetches(&text);
+        assert_eq!(dropped, 1);
+        assert_eq!(
+            kept,
+            lines[1..].iter().map(String::as_str).collect::<Vec<_>>()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn rank_prefers_related_pages_and_latest_fetch() {
+        let chunks = vec![
+            chunk("https://a.example/", "borrow checker lifetimes in rust", 0),
+            chunk("https://b.example/", "sourdough bread baking hydration", 0),
+            chunk("https://c.example/", "rust lifetimes explained", 0),
+            chunk("https://c.example/", "release notes for a gardening app", 5),
+        ];
+        let found = rank(chunks, &hash_embedding("rust lifetimes"), 5);
+        let urls: Vec<&str> = found.iter().map(|r| r.url.as_str()).collect();
+        assert_eq!(urls, ["https://a.example/"]);
+    }
+}
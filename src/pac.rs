@@ -0,0 +1,147 @@
+use anyhow::{Context, Result};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+
+use crate::{rules::DomainRules, PROXY_PORT};
+
+// ------------ proxy auto-config ------------------------------------------
+/// Renders a PAC script that sends captured domains through the proxy and
+/// everything else `DIRECT`, using the same rules as the logger.
+pub fn render_pac(rules: &DomainRules, proxy_host: &str) -> String {
+    let allow = serde_json::to_string(&rules.allow).unwrap_or_else(|_| "[]".to_string());
+    let deny = serde_json::to_string(&rules.deny).unwrap_or_else(|_| "[]".to_string());
+    format!(
+        r#"// Generated by digital-twin-proxy from domains.conf
+function FindProxyForURL(url, host) {{
+  var allow = {allow};
+  var deny = {deny};
+  host = host.toLowerCase();
+
+  function matches(domains) {{
+    for (var i = 0; i < domains.length; i++) {{
+      if (host === domains[i] || dnsDomainIs(host, "." + domains[i])) {{
+        return true;
+      }}
+    }}
+    return false;
+  }}
+
+  if (isPlainHostName(host) || host === "127.0.0.1" || host === "localhost") {{
+    return "DIRECT";
+  }}
+  if (matches(deny)) {{
+    return "DIRECT";
+  }}
+  if (allow.length > 0 && !matches(allow)) {{
+    return "DIRECT";
+  }}
+  return "PROXY {proxy_host}:{PROXY_PORT}; DIRECT";
+}}
+"#
+    )
+}
+
+/// Serves the PAC file over HTTP. The rules are re-read on every request so
+/// edits to domains.conf apply without a restart.
+pub async fn serve(port: u16, proxy_host: String) -> Result<()> {
+    let listener = TcpListener::bind(("127.0.0.1", port))
+        .await
+        .with_context(|| format!("Failed to bind PAC server on port {port}"))?;
+    println!("PAC file served at http://127.0.0.1:{port}/proxy.pac");
+
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let proxy_host = proxy_host.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle_request(stream, &proxy_host).await {
+                eprintln!("PAC request failed: {e}");
+            }
+        });
+    }
+}
+
+async fn handle_request(mut stream: TcpStream, proxy_host: &str) -> Result<()> {
+    let mut buf = vec![0u8; 4096];
+    let n = stream.read(&mut buf).await?;
+    let request = String::from_utf8_lossy(&buf[..n]);
+    let path = request
+        .lines()
+        .next()
+        .and_then(|line| line.split_whitespace().nth(1))
+        .unwrap_or("/");
+
+    let response = if matches!(path, "/" | "/proxy.pac" | "/wpad.dat") {
+        match DomainRules::load() {
+            Ok(rules) => {
+                let body = render_pac(&rules, proxy_host);
+                format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: application/x-ns-proxy-autoconfig\r\n\
+                     Cache-Control: no-cache\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                    body.len()
+                )
+            }
+            // Answer anyway, so the browser falls back instead of waiting on the socket
+            Err(e) => {
+                eprintln!("PAC request failed: {e:#}");
+                let body = format!("{e:#}\n");
+                format!(
+                    "HTTP/1.1 500 Internal Server Error\r\nContent-Type: text/plain\r\n\
+                     Content-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                    body.len()
+                )
+            }
+        }
+    } else {
+        "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string()
+    };
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules(allow: &[&str], deny: &[&str]) -> DomainRules {
+        DomainRules {
+            allow: allow.iter().map(|d| (*d).to_string()).collect(),
+            deny: deny.iter().map(|d| (*d).to_string()).collect(),
+            ..DomainRules::default()
+        }
+    }
+
+    #[test]
+    fn render_pac_embeds_allow_and_deny_lists() {
+        let pac = render_pac(
+            &rules(&["github.com", "docs.rs"], &["bank.example"]),
+            "10.0.0.5",
+        );
+        assert!(pac.contains(r#"var allow = ["github.com","docs.rs"];"#));
+        assert!(pac.contains(r#"var deny = ["bank.example"];"#));
+        assert!(pac.contains(&format!(r#"return "PROXY 10.0.0.5:{PROXY_PORT}; DIRECT";"#)));
+    }
+
+    #[test]
+    fn render_pac_sends_local_denied_and_unlisted_hosts_direct() {
+        let pac = render_pac(&rules(&[], &[]), "127.0.0.1");
+        assert!(pac.contains("var allow = [];"));
+        assert!(pac.contains("var deny = [];"));
+        // Local hosts, then deny matches, then anything outside a non-empty allow list
+        let direct = pac.matches(r#"return "DIRECT";"#).count();
+        assert_eq!(direct, 3);
+        let order: Vec<usize> = [
+            "isPlainHostName(host)",
+            "if (matches(deny))",
+            "if (allow.length > 0 && !matches(allow))",
+            "return \"PROXY",
+        ]
+        .iter()
+        .filter_map(|needle| pac.find(needle))
+        .collect();
+        assert_eq!(order.len(), 4);
+        assert!(order.is_sorted());
+    }
+}
@@ -0,0 +1,208 @@
+use anyhow::{Context, Result};
+use std::fs;
+
+use crate::{config_dir, LogEntry};
+
+// ------------ personas -----------------------------------------------------
+const PERSONAS_FILE: &str = "personas.conf";
+
+/// Named groups of client addresses and proxy-auth usernames, so that a proxy
+/// shared by several devices or people keeps a separate summary for each.
+#[derive(Default)]
+pub struct Personas {
+    personas: Vec<(String, Vec<String>)>,
+}
+
+impl Personas {
+    /// Loads `personas.conf` from the config dir. Each line is
+    /// `<persona> <client address or username>...`; a missing file means no personas.
+    pub fn load() -> Result<Self> {
+        let path = config_dir()?.join(PERSONAS_FILE);
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        };
+        Self::parse(&contents).with_context(|| format!("Invalid persona in {}", path.display()))
+    }
+
+    fn parse(contents: &str) -> Result<Self> {
+        let mut personas: Vec<(String, Vec<String>)> = Vec::new();
+        for (n, line) in contents.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            let mut parts = line.split_whitespace();
+            let Some(name) = parts.next() else {
+                continue;
+            };
+            if !is_valid_name(name) {
+                anyhow::bail!(
+                    "line {}: persona names may only contain letters, digits, '-' and '_'",
+                    n + 1
+                );
+            }
+            if personas.iter().any(|(other, _)| other == name) {
+                anyhow::bail!("line {}: persona `{name}` is defined twice", n + 1);
+            }
+            let members: Vec<String> = parts.map(str::to_string).collect();
+            if members.is_empty() {
+                anyhow::bail!("line {}: persona `{name}` has no clients", n + 1);
+            }
+            // Every client resolves to exactly one persona, and a name can't
+            // stand for someone else's client
+            for (other, other_members) in &personas {
+                if let Some(m) = members.iter().find(|m| other_members.contains(m)) {
+                    anyhow::bail!("line {}: `{m}` already belongs to persona `{other}`", n + 1);
+                }
+                if other_members.iter().any(|m| m == name) {
+                    anyhow::bail!("line {}: `{name}` is a client of persona `{other}`", n + 1);
+                }
+                if members.contains(other) {
+                    anyhow::bail!("line {}: `{other}` is already a persona", n + 1);
+                }
+            }
+            personas.push((name.to_string(), members));
+        }
+        Ok(Self { personas })
+    }
+
+    /// The persona an entry belongs to. Proxy-auth usernames take precedence
+    /// over client addresses.
+    pub fn resolve(&self, entry: &LogEntry) -> Option<&str> {
+        [entry.user.as_deref(), entry.client.as_deref()]
+            .into_iter()
+            .flatten()
+            .find_map(|id| self.persona_of(id))
+    }
+
+    fn persona_of(&self, id: &str) -> Option<&str> {
+        self.personas
+            .iter()
+            .find(|(_, members)| members.iter().any(|m| m == id))
+            .map(|(name, _)| name.as_str())
+    }
+
+    /// The persona a `--client` filter names or belongs to.
+    pub fn persona_for(&self, filter: &str) -> Option<&str> {
+        self.personas
+            .iter()
+            .find(|(name, _)| name == filter)
+            .map(|(name, _)| name.as_str())
+            .or_else(|| self.persona_of(filter))
+    }
+
+    /// Maps a `--client` filter (a persona name, address or username) to the
+    /// key its summary is stored under. Filters that aren't safe in a file
+    /// name are refused rather than rewritten, so two never share a summary.
+    pub fn summary_key(&self, filter: &str) -> Result<String> {
+        if let Some(persona) = self.persona_for(filter) {
+            return Ok(persona.to_string());
+        }
+        if filter.starts_with('.')
+            || !filter
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | ':'))
+        {
+            anyhow::bail!(
+                "`{filter}` can't be used as a summary name; add it to a persona in {PERSONAS_FILE}"
+            );
+        }
+        Ok(filter.to_string())
+    }
+
+    /// Whether an entry matches a `--client` filter. A persona, or any of its
+    /// members, matches all of the persona's traffic; any other address or
+    /// username only matches traffic that belongs to no persona.
+    pub fn matches(&self, filter: &str, entry: &LogEntry) -> bool {
+        let persona = self.resolve(entry);
+        self.persona_for(filter).map_or_else(
+            || {
+                persona.is_none()
+                    && (entry.client.as_deref() == Some(filter)
+                        || entry.user.as_deref() == Some(filter))
+            },
+            |wanted| persona == Some(wanted),
+        )
+    }
+}
+
+fn is_valid_name(name: &str) -> bool {
+    name.chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    fn entry(client: Option<&str>, user: Option<&str>) -> LogEntry {
+        LogEntry {
+            client: client.map(str::to_string),
+            user: user.map(str::to_string),
+            ..LogEntry::new("http://example.com/".to_string(), Utc::now())
+        }
+    }
+
+    #[test]
+    fn parse_rejects_malformed_and_colliding_personas() {
+        let error = |contents: &str| parse_error(contents).unwrap_or_default();
+        assert!(error("alice 10.0.0.1\nbad.name 10.0.0.2\n").starts_with("line 2:"));
+        assert!(error("lonely # no clients\n").contains("has no clients"));
+        assert!(error("alice 10.0.0.1\nalice 10.0.0.2\n").contains("defined twice"));
+        assert!(error("alice 10.0.0.1\nbob 10.0.0.1\n").contains("already belongs"));
+        assert!(error("alice bob\nbob 10.0.0.2\n").contains("is a client of"));
+        assert!(error("bob 10.0.0.2\nalice bob\n").contains("already a persona"));
+        assert!(Personas::parse("# comment\n\nalice 10.0.0.1 alice\n").is_ok());
+    }
+
+    fn parse_error(contents: &str) -> Option<String> {
+        Personas::parse(contents).err().map(|e| e.to_string())
+    }
+
+    #[test]
+    fn resolve_prefers_the_username_over_the_address() {
+        let personas = Personas::parse("alice 10.0.0.1 alice\nbob 10.0.0.2\n").unwrap_or_default();
+        assert_eq!(
+            personas.resolve(&entry(Some("10.0.0.1"), None)),
+            Some("alice")
+        );
+        assert_eq!(
+            personas.resolve(&entry(Some("10.0.0.2"), Some("alice"))),
+            Some("alice")
+        );
+        // An unknown username falls back to the address
+        assert_eq!(
+            personas.resolve(&entry(Some("10.0.0.2"), Some("carol"))),
+            Some("bob")
+        );
+        assert_eq!(personas.resolve(&entry(Some("10.0.0.9"), None)), None);
+        assert_eq!(personas.resolve(&entry(None, None)), None);
+    }
+
+    #[test]
+    fn matches_selects_a_whole_persona_or_only_unmapped_traffic() {
+        let personas = Personas::parse("alice 10.0.0.1 10.0.0.3\n").unwrap_or_default();
+        let laptop = entry(Some("10.0.0.3"), None);
+        let guest = entry(Some("10.0.0.9"), Some("carol"));
+        // A member address stands for its whole persona
+        assert!(personas.matches("alice", &laptop));
+        assert!(personas.matches("10.0.0.1", &laptop));
+        assert!(!personas.matches("alice", &guest));
+        assert!(personas.matches("10.0.0.9", &guest));
+        assert!(personas.matches("carol", &guest));
+        // Alice's traffic never lands in an unmapped username's summary
+        assert!(!personas.matches("carol", &entry(Some("10.0.0.1"), Some("carol"))));
+    }
+
+    #[test]
+    fn summary_key_refuses_filters_it_would_have_to_rewrite() {
+        let personas = Personas::parse("alice 10.0.0.1\n").unwrap_or_default();
+        assert_eq!(
+            personas.summary_key("10.0.0.1").ok().as_deref(),
+            Some("alice")
+        );
+        assert_eq!(personas.summary_key("::1").ok().as_deref(), Some("::1"));
+        assert!(personas.summary_key("../evil").is_err());
+        assert!(personas.summary_key("a b").is_err());
+    }
+}
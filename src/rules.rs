@@ -0,0 +1,122 @@
+use anyhow::{Context, Result};
+use std::fs;
+
+use crate::config_dir;
+
+// ------------ domain rules -------------------------------------------------
+const RULES_FILE: &str = "domains.conf";
+
+const RULES_TEMPLATE: &str = "# Domain rules for digital-twin-proxy
+#
+# One rule per line: <allow|deny|no-bump> <domain>
+# A domain matches itself and all of its subdomains.
+#
+#   allow <domain>    only capture these domains (if any allow rules exist)
+#   deny <domain>     never capture these domains
+#   no-bump <domain>  capture, but never TLS-intercept (only the host is logged)
+#
+# deny mybank.example
+# no-bump accounts.google.com
+";
+
+/// Allow/deny lists shared by the logger, the TLS interception config and
+/// anything else that needs to decide whether a host is captured.
+#[derive(Default, Clone)]
+pub struct DomainRules {
+    pub allow: Vec<String>,
+    pub deny: Vec<String>,
+    pub no_bump: Vec<String>,
+}
+
+impl DomainRules {
+    pub fn path() -> Result<std::path::PathBuf> {
+        Ok(config_dir()?.join(RULES_FILE))
+    }
+
+    /// Loads the rules file, writing a commented template on first use.
+    pub fn load() -> Result<Self> {
+        let path = Self::path()?;
+        if !path.exists() {
+            fs::write(&path, RULES_TEMPLATE).context("Failed to write domain rules template")?;
+        }
+        let contents = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        Self::parse(&contents).with_context(|| format!("Invalid rule in {}", path.display()))
+    }
+
+    /// Like `load`, but without writing the template; a missing file means no rules.
+    pub fn read() -> Result<Self> {
+        let path = Self::path()?;
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        };
+        Self::parse(&contents).with_context(|| format!("Invalid rule in {}", path.display()))
+    }
+
+    fn parse(contents: &str) -> Result<Self> {
+        let mut rules = Self::default();
+        for (n, line) in contents.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let mut parts = line.split_whitespace();
+            let (Some(kind), Some(domain), None) = (parts.next(), parts.next(), parts.next())
+            else {
+                anyhow::bail!("line {}: expected `<allow|deny|no-bump> <domain>`", n + 1);
+            };
+            let domain = normalize_domain(domain);
+            match kind {
+                "allow" => rules.allow.push(domain),
+                "deny" => rules.deny.push(domain),
+                "no-bump" => rules.no_bump.push(domain),
+                other => anyhow::bail!("line {}: unknown rule `{other}`", n + 1),
+            }
+        }
+        Ok(rules)
+    }
+
+    /// Whether traffic to `host` should be recorded at all.
+    pub fn captures(&self, host: &str) -> bool {
+        !matches_any(&self.deny, host) && (self.allow.is_empty() || matches_any(&self.allow, host))
+    }
+
+    /// Whether HTTPS traffic to `host` may be decrypted in TLS interception mode.
+    pub fn bumps(&self, host: &str) -> bool {
+        self.captures(host) && !matches_any(&self.no_bump, host)
+    }
+}
+
+fn normalize_domain(domain: &str) -> String {
+    domain
+        .trim_start_matches("*.")
+        .trim_start_matches('.')
+        .trim_end_matches('.')
+        .to_ascii_lowercase()
+}
+
+fn matches_any(domains: &[String], host: &str) -> bool {
+    let host = host.trim_end_matches('.').to_ascii_lowercase();
+    domains.iter().any(|d| in_domain(&host, d))
+}
+
+/// Whether `host` is `domain` or one of its subdomains (both lowercase).
+pub fn in_domain(host: &str, domain: &str) -> bool {
+    host == domain
+        || host
+            .strip_suffix(domain)
+            .is_some_and(|prefix| prefix.ends_with('.'))
+}
+
+/// Extracts the lowercase host from a logged URL (or a bare `host:port`).
+pub fn host_of(url: &str) -> Option<String> {
+    url::Url::parse(url)
+        .ok()
+        .and_then(|u| u.host_str().map(str::to_ascii_lowercase))
+        .or_else(|| {
+            let host = url.split(':').next()?;
+            (!host.is_empty()).then(|| host.to_ascii_lowercase())
+        })
+}
@@ -0,0 +1,313 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local, Utc};
+use clap::Args;
+use regex::{Regex, RegexBuilder};
+use serde::Serialize;
+use std::collections::HashMap;
+
+use crate::{
+    aggregate::normalize_url,
+    parse_since, read_log_since,
+    rules::{host_of, in_domain},
+    session, storage, LogEntry,
+};
+
+// ------------ search -------------------------------------------------------
+/// Characters of page text shown on each side of a match.
+const SNIPPET_CONTEXT: usize = 60;
+
+#[derive(Args)]
+pub struct SearchArgs {
+    /// Text to look for in URLs, titles and cached page text (case-insensitive)
+    query: String,
+    /// Start of the window (<duration> ago or RFC 3339 time)
+    #[arg(short, long)]
+    since: Option<String>,
+    /// End of the window (<duration> ago or RFC 3339 time)
+    #[arg(short, long)]
+    until: Option<String>,
+    /// Only match requests to this host or its subdomains
+    #[arg(long)]
+    host: Option<String>,
+    /// Only match this status code, or a class like 4xx
+    #[arg(long, value_parser = parse_status)]
+    status: Option<StatusFilter>,
+    /// Treat the query as a regular expression
+    #[arg(short, long)]
+    regex: bool,
+    /// Print matches as JSON
+    #[arg(long)]
+    json: bool,
+}
+
+#[derive(Clone, Copy)]
+enum StatusFilter {
+    Code(u16),
+    /// The hundreds digit, e.g. 4 for `4xx`
+    Class(u16),
+}
+
+impl StatusFilter {
+    const fn matches(self, status: Option<u16>) -> bool {
+        match (self, status) {
+            (Self::Code(code), Some(status)) => status == code,
+            (Self::Class(class), Some(status)) => status / 100 == class,
+            (_, None) => false,
+        }
+    }
+}
+
+fn parse_status(input: &str) -> Result<StatusFilter, String> {
+    let invalid = || format!("invalid status {input:?} (expected e.g. 404 or 4xx)");
+    let lower = input.to_ascii_lowercase();
+    if let Some(class) = lower.strip_suffix("xx") {
+        return match class.parse() {
+            Ok(class @ 1..=5) => Ok(StatusFilter::Class(class)),
+            _ => Err(invalid()),
+        };
+    }
+    match lower.parse() {
+        Ok(code @ 100..=599) => Ok(StatusFilter::Code(code)),
+        _ => Err(invalid()),
+    }
+}
+
+#[derive(Serialize)]
+struct Hit {
+    ts: DateTime<Utc>,
+    url: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    status: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    title: Option<String>,
+    /// Cached page text around the match, when only the page text matched
+    #[serde(skip_serializing_if = "Option::is_none")]
+    snippet: Option<String>,
+    /// Requests to this URL in the session
+    count: usize,
+}
+
+#[derive(Serialize)]
+struct SessionHits {
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    primary_domain: String,
+    requests: usize,
+    hits: Vec<Hit>,
+}
+
+fn host_matches(url: &str, wanted: &str) -> bool {
+    host_of(url).is_some_and(|host| in_domain(&host, wanted))
+}
+
+/// The match in `text` with some context, whitespace collapsed.
+fn snippet(text: &str, pattern: &Regex) -> Option<String> {
+    let found = pattern.find(text)?;
+    let mut start = found.start().saturating_sub(SNIPPET_CONTEXT);
+    while !text.is_char_boundary(start) {
+        start -= 1;
+    }
+    let mut end = (found.end() + SNIPPET_CONTEXT).min(text.len());
+    while !text.is_char_boundary(end) {
+        end += 1;
+    }
+    let mut out = text[start..end]
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ");
+    if start > 0 {
+        out.insert(0, '…');
+    }
+    if end < text.len() {
+        out.push('…');
+    }
+    Some(out)
+}
+
+struct Filter {
+    host: Option<String>,
+    status: Option<StatusFilter>,
+    pattern: Regex,
+}
+
+impl Filter {
+    /// Matches in one session, one per URL, in the order they were first requested.
+    fn hits(&self, entries: &[&LogEntry], pages: &HashMap<String, String>) -> Vec<Hit> {
+        let pattern = &self.pattern;
+        let mut hits: Vec<Hit> = Vec::new();
+        let mut seen: HashMap<String, usize> = HashMap::new();
+        for entry in entries {
+            if self
+                .host
+                .as_deref()
+                .is_some_and(|h| !host_matches(&entry.url, h))
+                || self.status.is_some_and(|s| !s.matches(entry.status))
+            {
+                continue;
+            }
+            let key = normalize_url(&entry.url);
+            if let Some(&i) = seen.get(&key) {
+                let hit = &mut hits[i];
+                hit.count += 1;
+                hit.status = hit.status.or(entry.status);
+                if hit.title.is_none() {
+                    hit.title.clone_from(&entry.title);
+                }
+                continue;
+            }
+            let in_url = pattern.is_match(&entry.url)
+                || entry.title.as_deref().is_some_and(|t| pattern.is_match(t));
+            let snippet = if in_url {
+                None
+            } else {
+                match pages.get(&key).and_then(|text| snippet(text, pattern)) {
+                    Some(snippet) => Some(snippet),
+                    None => continue,
+                }
+            };
+            seen.insert(key, hits.len());
+            hits.push(Hit {
+                ts: entry.ts,
+                url: entry.url.clone(),
+                status: entry.status,
+                title: entry.title.clone(),
+                snippet,
+                count: 1,
+            });
+        }
+        hits
+    }
+}
+
+fn print_sessions(sessions: &[SessionHits]) {
+    for session in sessions {
+        let start = session.start.with_timezone(&Local);
+        let end = session.end.with_timezone(&Local);
+        println!(
+            "{} – {}  {}  ({} request(s))",
+            start.format("%Y-%m-%d %H:%M"),
+            end.format(if start.date_naive() == end.date_naive() {
+                "%H:%M"
+            } else {
+                "%Y-%m-%d %H:%M"
+            }),
+            session.primary_domain,
+            session.requests
+        );
+        for hit in &session.hits {
+            let status = hit
+                .status
+                .map_or_else(|| "-".to_string(), |s| s.to_string());
+            print!(
+                "  {}  {status:>3}  {}",
+                hit.ts.with_timezone(&Local).format("%H:%M"),
+                hit.url
+            );
+            if hit.count > 1 {
+                print!(" ×{}", hit.count);
+            }
+            println!();
+            if let Some(title) = &hit.title {
+                println!("              {title}");
+            }
+            if let Some(snippet) = &hit.snippet {
+                println!("              {snippet}");
+            }
+        }
+        println!();
+    }
+    let hits: usize = sessions.iter().map(|s| s.hits.len()).sum();
+    println!("{hits} match(es) in {} session(s)", sessions.len());
+}
+
+/// Searches the log for `query`, grouping matches by browsing session.
+pub fn run_search(args: &SearchArgs) -> Result<()> {
+    let since = args.since.as_deref().map(parse_since).transpose()?;
+    let until = args.until.as_deref().map(parse_since).transpose()?;
+    let query = if args.regex {
+        args.query.clone()
+    } else {
+        regex::escape(&args.query)
+    };
+    let pattern = RegexBuilder::new(&query)
+        .case_insensitive(true)
+        .build()
+        .with_context(|| format!("Invalid regex {:?}", args.query))?;
+    let filter = Filter {
+        host: args.host.as_deref().map(str::to_ascii_lowercase),
+        status: args.status,
+        pattern,
+    };
+
+    let log: Vec<LogEntry> = read_log_since(since)?
+        .into_iter()
+        .filter(|e| until.is_none_or(|u| e.ts < u))
+        .collect();
+    let pages = storage::read_pages()?;
+
+    let sessions: Vec<SessionHits> = session::split_sessions(&log)
+        .into_iter()
+        .filter_map(|entries| {
+            let hits = filter.hits(&entries, &pages);
+            if hits.is_empty() {
+                return None;
+            }
+            Some(SessionHits {
+                start: entries.first()?.ts,
+                end: entries.last()?.ts,
+                primary_domain: session::primary_domain(&entries),
+                requests: entries.len(),
+                hits,
+            })
+        })
+        .collect();
+
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&sessions)?);
+    } else if sessions.is_empty() {
+        println!("No matches");
+    } else {
+        print_sessions(&sessions);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_status_takes_codes_and_classes() {
+        let code = parse_status("404");
+        assert!(code.is_ok_and(|f| f.matches(Some(404)) && !f.matches(Some(400))));
+        let class = parse_status("5XX");
+        assert!(class.is_ok_and(|f| f.matches(Some(503)) && !f.matches(Some(404))));
+        assert!(parse_status("200").is_ok_and(|f| !f.matches(None)));
+    }
+
+    #[test]
+    fn parse_status_rejects_malformed_input() {
+        for input in [
+            "", "xx", "0xx", "6xx", "99", "600", "4x", "-404", "abc", "4xxx",
+        ] {
+            assert!(parse_status(input).is_err(), "{input:?} was accepted");
+        }
+    }
+
+    #[test]
+    fn snippet_trims_to_the_match_on_char_boundaries() -> Result<()> {
+        let pattern = Regex::new("ne+dle")?;
+        let text = format!("{} needle {}", "é".repeat(100), "ü".repeat(100));
+        let found = snippet(&text, &pattern).unwrap_or_default();
+        assert!(found.starts_with('…') && found.ends_with('…'));
+        assert!(found.contains("needle"));
+        assert!(found.chars().count() < text.chars().count());
+
+        assert_eq!(
+            snippet("a  needle\nhere", &pattern).as_deref(),
+            Some("a needle here")
+        );
+        assert_eq!(snippet("nothing to see", &pattern), None);
+        Ok(())
+    }
+}
@@ -0,0 +1,153 @@
+use anyhow::{Context, Result};
+use std::{fs, path::PathBuf, process::Command};
+
+// ------------ API key sources ----------------------------------------------
+#[cfg(feature = "keyring")]
+const KEYRING_SERVICE: &str = "digital-twin-proxy";
+
+/// Where the API key comes from. Nothing is read until a request needs it,
+/// so rotated keys and short-lived tokens are picked up without a restart.
+#[derive(Clone)]
+pub enum ApiKey {
+    Value(String),
+    File(PathBuf),
+    /// A shell command printing the key, e.g. `pass show openai`
+    Command(String),
+    /// The key `login` stored in the OS keyring for this API base, if any
+    Keyring(String),
+    /// A key already looked up, reused for the requests of one summary
+    Resolved(Option<String>),
+}
+
+impl ApiKey {
+    pub fn resolve(&self) -> Result<Option<String>> {
+        match self {
+            Self::Value(key) => Ok(Some(key.clone())),
+            Self::File(path) => {
+                let key = fs::read_to_string(path)
+                    .with_context(|| format!("Failed to read API key file {}", path.display()))?;
+                let key = key.trim();
+                if key.is_empty() {
+                    anyhow::bail!("API key file {} is empty", path.display());
+                }
+                Ok(Some(key.to_string()))
+            }
+            Self::Command(command) => run_key_command(command).map(Some),
+            Self::Keyring(api_base) => keyring_get(api_base),
+            Self::Resolved(key) => Ok(key.clone()),
+        }
+    }
+
+    /// `resolve` on a blocking thread, for use inside the async summarizer.
+    pub async fn resolve_async(&self) -> Result<Option<String>> {
+        let key = self.clone();
+        tokio::task::spawn_blocking(move || key.resolve()).await?
+    }
+}
+
+/// Runs `command` through the shell and takes the first line it prints.
+fn run_key_command(command: &str) -> Result<String> {
+    let mut shell = if cfg!(windows) {
+        let mut shell = Command::new("cmd");
+        shell.arg("/C");
+        shell
+    } else {
+        let mut shell = Command::new("sh");
+        shell.arg("-c");
+        shell
+    };
+    // stderr stays attached so password managers can prompt
+    let output = shell
+        .arg(command)
+        .stderr(std::process::Stdio::inherit())
+        .output()
+        .with_context(|| format!("Failed to run API key command `{command}`"))?;
+    if !output.status.success() {
+        anyhow::bail!("API key command `{command}` failed with {}", output.status);
+    }
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let key = stdout.lines().next().unwrap_or_default().trim();
+    if key.is_empty() {
+        anyhow::bail!("API key command `{command}` printed nothing");
+    }
+    Ok(key.to_string())
+}
+
+#[cfg(feature = "keyring")]
+fn keyring_entry(api_base: &str) -> Result<keyring::Entry> {
+    Ok(keyring::Entry::new(
+        KEYRING_SERVICE,
+        api_base.trim_end_matches('/'),
+    )?)
+}
+
+#[cfg(feature = "keyring")]
+fn keyring_get(api_base: &str) -> Result<Option<String>> {
+    match keyring_entry(api_base)?.get_password() {
+        Ok(key) => Ok(Some(key)),
+        Err(keyring::Error::NoEntry) => Ok(None),
+        Err(e) => Err(e).context("Failed to read the API key from the OS keyring"),
+    }
+}
+
+#[cfg(not(feature = "keyring"))]
+#[allow(clippy::unnecessary_wraps)]
+const fn keyring_get(_api_base: &str) -> Result<Option<String>> {
+    Ok(None)
+}
+
+/// Reads a line from stdin, with echo turned off when it's a terminal.
+#[cfg(feature = "keyring")]
+fn prompt_secret(prompt: &str) -> Result<String> {
+    use std::io::{BufRead, IsTerminal, Write};
+
+    let set_echo = |on: bool| {
+        let _ = Command::new("stty")
+            .arg(if on { "echo" } else { "-echo" })
+            .status();
+    };
+    let stdin = std::io::stdin();
+    let tty = stdin.is_terminal();
+    if tty {
+        eprint!("{prompt}");
+        std::io::stderr().flush()?;
+        set_echo(false);
+    }
+    let mut line = String::new();
+    let result = stdin.lock().read_line(&mut line);
+    if tty {
+        set_echo(true);
+        eprintln!();
+    }
+    result?;
+    Ok(line.trim().to_string())
+}
+
+/// Stores (or with `delete`, removes) the API key for `api_base` in the OS keyring.
+#[cfg(feature = "keyring")]
+pub fn run_login(api_base: &str, delete: bool) -> Result<()> {
+    let entry = keyring_entry(api_base)?;
+    if delete {
+        match entry.delete_credential() {
+            Ok(()) => println!("Removed the API key for {api_base} from the OS keyring"),
+            Err(keyring::Error::NoEntry) => println!("No API key stored for {api_base}"),
+            Err(e) => return Err(e.into()),
+        }
+        return Ok(());
+    }
+    let key = prompt_secret(&format!("API key for {api_base}: "))?;
+    if key.is_empty() {
+        anyhow::bail!("No API key entered");
+    }
+    entry.set_password(&key)?;
+    println!("Stored the API key for {api_base} in the OS keyring");
+    Ok(())
+}
+
+#[cfg(not(feature = "keyring"))]
+pub fn run_login(_api_base: &str, _delete: bool) -> Result<()> {
+    anyhow::bail!(
+        "This build has no keyring support; rebuild with `cargo build --release --features keyring`, \
+         or use --api-key-file / --api-key-cmd"
+    )
+}
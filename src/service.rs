@@ -0,0 +1,480 @@
+use anyhow::{Context, Result};
+use directories::BaseDirs;
+use std::{
+    fmt::Write as _,
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use crate::{
+    config::{self, Settings},
+    config_dir, data_dir,
+    storage::restrict_permissions,
+};
+
+// ------------ background service -------------------------------------------
+const SERVICE_NAME: &str = "digital-twin-proxy";
+const LAUNCHD_LABEL: &str = "rs.ai-proxy.digital-twin-proxy";
+const SERVICE_ENV_FILE: &str = "service.env";
+const SERVICE_LOG_FILE: &str = "ambient.log";
+
+/// Settings copied from the installing shell (and `.env`) into the service.
+const SERVICE_ENV_VARS: &[&str] = &[
+    "API_BASE",
+    "API_KEY_FILE",
+    "API_KEY_CMD",
+    "MODEL",
+    "EMBEDDING_MODEL",
+    "AMBIENT_INTERVAL",
+    "MAX_ANALYSIS_ITEMS",
+    "ROLLUP_DEPTH",
+    "TLS_INTERCEPT",
+    "PAC_PORT",
+    "ENCRYPTION_KEY_FILE",
+    "CONFIG_PROFILE",
+    // Used to find Squid when it isn't in a standard location
+    "PATH",
+];
+
+/// Secrets never written into the service settings, each with the settings
+/// that can point the service at it instead and how to switch to them.
+const SERVICE_SECRETS: &[(&str, &[&str], &str)] = &[
+    (
+        "API_KEY",
+        &["API_KEY_FILE", "API_KEY_CMD"],
+        "put the key in a file only you can read and set API_KEY_FILE to its path, \
+         or set API_KEY_CMD to a command that prints it (e.g. `pass show openai`)",
+    ),
+    (
+        "ENCRYPTION_PASSPHRASE",
+        &["ENCRYPTION_KEY_FILE"],
+        "move the store to a key file with `digital-twin-proxy rekey --new-key-file <path>` \
+         and set ENCRYPTION_KEY_FILE to that path",
+    ),
+];
+
+/// Which service manager runs `ambient` on this platform.
+#[derive(Clone, Copy)]
+enum Manager {
+    Systemd,
+    Launchd,
+}
+
+impl Manager {
+    const fn current() -> Self {
+        if cfg!(target_os = "macos") {
+            Self::Launchd
+        } else {
+            Self::Systemd
+        }
+    }
+
+    fn unit_path(self) -> Result<PathBuf> {
+        let dirs = BaseDirs::new().context("Could not determine home directory")?;
+        Ok(match self {
+            Self::Systemd => dirs
+                .config_dir()
+                .join("systemd/user")
+                .join(format!("{SERVICE_NAME}.service")),
+            Self::Launchd => dirs
+                .home_dir()
+                .join("Library/LaunchAgents")
+                .join(format!("{LAUNCHD_LABEL}.plist")),
+        })
+    }
+}
+
+/// The service settings taken from the current environment, in `SERVICE_ENV_VARS` order.
+fn current_env() -> Vec<(String, String)> {
+    SERVICE_ENV_VARS
+        .iter()
+        .filter_map(|&key| Some((key.to_string(), std::env::var(key).ok()?)))
+        .collect()
+}
+
+/// Refuses to install while a secret is only available as a raw value,
+/// since the service settings would hold it in plain text. When a file or
+/// command is configured as well, the service uses that instead.
+fn check_secrets(env: &[(String, String)], is_set: impl Fn(&str) -> bool) -> Result<()> {
+    for &(secret, references, how) in SERVICE_SECRETS {
+        if !is_set(secret) {
+            continue;
+        }
+        match references
+            .iter()
+            .find(|&&r| env.iter().any(|(key, _)| key == r))
+        {
+            Some(reference) => println!("Not copying {secret}; the service reads {reference}"),
+            None => anyhow::bail!(
+                "{secret} would be stored in plain text in the service settings. Instead, \
+                 {how}, then unset {secret} and run `service install` again"
+            ),
+        }
+    }
+    Ok(())
+}
+
+/// Quotes a value for a systemd `EnvironmentFile` or `ExecStart` line.
+fn systemd_quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Escapes `%` so systemd doesn't expand it as a specifier in a unit file.
+/// `EnvironmentFile=` takes the rest of the line as the path without
+/// unquoting, so this is all a path there needs.
+fn systemd_escape_specifiers(value: &str) -> String {
+    value.replace('%', "%%")
+}
+
+fn xml_escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn render_env_file(env: &[(String, String)]) -> String {
+    let mut out = String::from("# Generated by `digital-twin-proxy service install`\n");
+    for (key, value) in env {
+        let _ = writeln!(out, "{key}={}", systemd_quote(value));
+    }
+    out
+}
+
+/// A systemd user unit running `ambient`, logging to the journal and
+/// restarted if it exits with an error.
+fn render_systemd_unit(exe: &Path, env_file: &Path) -> String {
+    format!(
+        "[Unit]
+Description=Digital Twin Proxy (ambient mode)
+After=network-online.target
+Wants=network-online.target
+
+[Service]
+Type=simple
+ExecStart={} ambient
+EnvironmentFile={}
+Restart=on-failure
+RestartSec=10
+# Leave time for Squid to shut down and the access log to drain
+TimeoutStopSec=30
+StandardOutput=journal
+StandardError=journal
+SyslogIdentifier={SERVICE_NAME}
+
+[Install]
+WantedBy=default.target
+",
+        systemd_escape_specifiers(&systemd_quote(&exe.display().to_string())),
+        systemd_escape_specifiers(&env_file.display().to_string())
+    )
+}
+
+/// A launchd agent running `ambient` at login, restarted unless it exits cleanly.
+fn render_launchd_plist(exe: &Path, env: &[(String, String)], log: &Path) -> String {
+    let mut vars = String::new();
+    for (key, value) in env {
+        let _ = write!(
+            vars,
+            "\n        <key>{}</key>\n        <string>{}</string>",
+            xml_escape(key),
+            xml_escape(value)
+        );
+    }
+    let log = xml_escape(&log.display().to_string());
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+    <key>Label</key>
+    <string>{LAUNCHD_LABEL}</string>
+    <key>ProgramArguments</key>
+    <array>
+        <string>{}</string>
+        <string>ambient</string>
+    </array>
+    <key>EnvironmentVariables</key>
+    <dict>{vars}
+    </dict>
+    <key>RunAtLoad</key>
+    <true/>
+    <key>KeepAlive</key>
+    <dict>
+        <key>SuccessfulExit</key>
+        <false/>
+    </dict>
+    <key>ExitTimeOut</key>
+    <integer>30</integer>
+    <key>StandardOutPath</key>
+    <string>{log}</string>
+    <key>StandardErrorPath</key>
+    <string>{log}</string>
+</dict>
+</plist>
+"#,
+        xml_escape(&exe.display().to_string())
+    )
+}
+
+/// Writes a file only the current user can read, since it may hold API keys.
+fn write_private(path: &Path, contents: &str) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, contents).with_context(|| format!("Failed to write {}", path.display()))?;
+    restrict_permissions(path)?;
+    Ok(())
+}
+
+/// Runs a service manager command, failing with its output if it doesn't succeed.
+fn run(program: &str, args: &[&str]) -> Result<()> {
+    let output = Command::new(program)
+        .args(args)
+        .output()
+        .with_context(|| format!("Failed to run {program}"))?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "`{program} {}` failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(())
+}
+
+pub fn run_install(settings: &Settings) -> Result<()> {
+    // Fail now rather than in a restart loop once the service is running
+    settings
+        .get::<String>(&config::API_BASE, None)?
+        .with_context(|| {
+            "API_BASE is not set; add it to .env, the environment or config.toml before installing"
+        })?;
+    let env = current_env();
+    check_secrets(&env, |key| std::env::var_os(key).is_some())?;
+    let exe = std::env::current_exe().context("Failed to locate the running binary")?;
+    let manager = Manager::current();
+    let unit_path = manager.unit_path()?;
+    let unit = unit_path.display().to_string();
+
+    match manager {
+        Manager::Systemd => {
+            let env_file = config_dir()?.join(SERVICE_ENV_FILE);
+            write_private(&env_file, &render_env_file(&env))?;
+            write_private(&unit_path, &render_systemd_unit(&exe, &env_file))?;
+            run("systemctl", &["--user", "daemon-reload"])?;
+            run(
+                "systemctl",
+                &[
+                    "--user",
+                    "enable",
+                    "--now",
+                    &format!("{SERVICE_NAME}.service"),
+                ],
+            )?;
+            println!("Installed and started {unit}");
+            println!("Settings: {}", env_file.display());
+            println!("Logs:     journalctl --user -u {SERVICE_NAME} -f");
+            println!("To keep it running while logged out: loginctl enable-linger \"$USER\"");
+        }
+        Manager::Launchd => {
+            let log = data_dir()?.join(SERVICE_LOG_FILE);
+            write_private(&unit_path, &render_launchd_plist(&exe, &env, &log))?;
+            // Replace a previously loaded copy; failing here just means there was none
+            let _ = run("launchctl", &["unload", &unit]);
+            run("launchctl", &["load", "-w", &unit])?;
+            println!("Installed and started {unit}");
+            println!("Logs: {}", log.display());
+        }
+    }
+    println!(
+        "Captured {}; run `service install` again after changing them \
+         (config.toml is read on every start)",
+        env.iter()
+            .map(|(key, _)| key.as_str())
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+    Ok(())
+}
+
+pub fn run_uninstall() -> Result<()> {
+    let manager = Manager::current();
+    let unit_path = manager.unit_path()?;
+    if !unit_path.exists() {
+        println!("No service installed at {}", unit_path.display());
+        return Ok(());
+    }
+    let unit = unit_path.display().to_string();
+    match manager {
+        Manager::Systemd => {
+            run(
+                "systemctl",
+                &[
+                    "--user",
+                    "disable",
+                    "--now",
+                    &format!("{SERVICE_NAME}.service"),
+                ],
+            )?;
+            fs::remove_file(&unit_path)?;
+            let _ = fs::remove_file(config_dir()?.join(SERVICE_ENV_FILE));
+            run("systemctl", &["--user", "daemon-reload"])?;
+        }
+        Manager::Launchd => {
+            run("launchctl", &["unload", "-w", &unit])?;
+            fs::remove_file(&unit_path)?;
+        }
+    }
+    println!("Stopped and removed {unit}");
+    Ok(())
+}
+
+pub fn run_status() -> Result<()> {
+    let manager = Manager::current();
+    let unit_path = manager.unit_path()?;
+    if !unit_path.exists() {
+        println!("Not installed (run `digital-twin-proxy service install`)");
+        return Ok(());
+    }
+    println!("Installed: {}", unit_path.display());
+    // The service manager prints its own status; a stopped unit is not an error here
+    let _ = match manager {
+        Manager::Systemd => Command::new("systemctl")
+            .args(["--user", "status", "--no-pager"])
+            .arg(format!("{SERVICE_NAME}.service"))
+            .status(),
+        Manager::Launchd => Command::new("launchctl")
+            .args(["list", LAUNCHD_LABEL])
+            .status(),
+    }
+    .context("Failed to query the service manager")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn env() -> Vec<(String, String)> {
+        vec![
+            (
+                "API_BASE".to_string(),
+                "http://localhost:11434/v1".to_string(),
+            ),
+            ("API_KEY_CMD".to_string(), r#"pass "a\c<&>""#.to_string()),
+        ]
+    }
+
+    #[test]
+    fn refuses_raw_secrets_without_a_reference() {
+        let raw = |key: &str| key == "API_KEY" || key == "ENCRYPTION_PASSPHRASE";
+        // `env()` sets API_KEY_CMD, which stands in for API_KEY
+        let err = check_secrets(&env(), raw).err().map(|e| e.to_string());
+        assert!(err.is_some_and(|e| e.starts_with("ENCRYPTION_PASSPHRASE would be stored")));
+        let err = check_secrets(&env()[..1], raw).err().map(|e| e.to_string());
+        assert!(err.is_some_and(|e| e.contains("set API_KEY_FILE")));
+        let key_file = [("ENCRYPTION_KEY_FILE".to_string(), "/k".to_string())];
+        assert!(check_secrets(&[env(), key_file.to_vec()].concat(), raw).is_ok());
+        assert!(check_secrets(&[], |_| false).is_ok());
+    }
+
+    #[test]
+    fn renders_env_file() {
+        assert_eq!(
+            render_env_file(&env()),
+            "# Generated by `digital-twin-proxy service install`\n\
+             API_BASE=\"http://localhost:11434/v1\"\n\
+             API_KEY_CMD=\"pass \\\"a\\\\c<&>\\\"\"\n"
+        );
+    }
+
+    #[test]
+    fn renders_systemd_unit() {
+        let unit = render_systemd_unit(
+            Path::new("/opt/my tools/digital-twin-proxy"),
+            Path::new("/home/me/.config/ai-proxy/service.env"),
+        );
+        assert_eq!(
+            unit,
+            "[Unit]
+Description=Digital Twin Proxy (ambient mode)
+After=network-online.target
+Wants=network-online.target
+
+[Service]
+Type=simple
+ExecStart=\"/opt/my tools/digital-twin-proxy\" ambient
+EnvironmentFile=/home/me/.config/ai-proxy/service.env
+Restart=on-failure
+RestartSec=10
+# Leave time for Squid to shut down and the access log to drain
+TimeoutStopSec=30
+StandardOutput=journal
+StandardError=journal
+SyslogIdentifier=digital-twin-proxy
+
+[Install]
+WantedBy=default.target
+"
+        );
+    }
+
+    #[test]
+    fn escapes_specifiers_in_systemd_paths() {
+        let unit = render_systemd_unit(
+            Path::new("/opt/100%/digital-twin-proxy"),
+            Path::new("/home/me/my config%h/service.env"),
+        );
+        assert!(unit.contains("ExecStart=\"/opt/100%%/digital-twin-proxy\" ambient\n"));
+        assert!(unit.contains("EnvironmentFile=/home/me/my config%%h/service.env\n"));
+    }
+
+    #[test]
+    fn renders_launchd_plist() {
+        let plist = render_launchd_plist(
+            Path::new("/usr/local/bin/digital-twin-proxy"),
+            &env(),
+            Path::new("/Users/me/Library/Application Support/ai-proxy/ambient.log"),
+        );
+        assert_eq!(
+            plist,
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+    <key>Label</key>
+    <string>rs.ai-proxy.digital-twin-proxy</string>
+    <key>ProgramArguments</key>
+    <array>
+        <string>/usr/local/bin/digital-twin-proxy</string>
+        <string>ambient</string>
+    </array>
+    <key>EnvironmentVariables</key>
+    <dict>
+        <key>API_BASE</key>
+        <string>http://localhost:11434/v1</string>
+        <key>API_KEY_CMD</key>
+        <string>pass &quot;a\c&lt;&amp;&gt;&quot;</string>
+    </dict>
+    <key>RunAtLoad</key>
+    <true/>
+    <key>KeepAlive</key>
+    <dict>
+        <key>SuccessfulExit</key>
+        <false/>
+    </dict>
+    <key>ExitTimeOut</key>
+    <integer>30</integer>
+    <key>StandardOutPath</key>
+    <string>/Users/me/Library/Application Support/ai-proxy/ambient.log</string>
+    <key>StandardErrorPath</key>
+    <string>/Users/me/Library/Application Support/ai-proxy/ambient.log</string>
+</dict>
+</plist>
+"#
+        );
+    }
+}
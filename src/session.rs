@@ -0,0 +1,298 @@
+use chrono::{DateTime, Duration as CDuration, Local, Utc};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Write,
+};
+
+use crate::{
+    aggregate::{aggregate_visits, AggregatedVisit, Visit},
+    rules::host_of,
+    CaptureEvent, LogEntry,
+};
+
+// ------------ sessions -----------------------------------------------------
+/// A gap longer than this always starts a new session.
+const IDLE_GAP_MINUTES: i64 = 15;
+/// After a shorter pause, a request to an unrelated host also starts a new session.
+const TOPIC_SWITCH_GAP_MINUTES: i64 = 4;
+
+const ASSET_EXTENSIONS: &[&str] = &[
+    ".js", ".mjs", ".css", ".map", ".png", ".jpg", ".jpeg", ".gif", ".svg", ".ico", ".webp",
+    ".avif", ".woff", ".woff2", ".ttf", ".otf", ".mp4", ".webm", ".m3u8", ".ts",
+];
+
+pub struct Session {
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    pub primary_domain: String,
+    pub requests: usize,
+    /// Page views with duplicates collapsed. Dwell is the time until the next
+    /// page view in the same session, so a page viewed last has none.
+    pub pages: Vec<AggregatedVisit>,
+}
+
+struct Builder<'a> {
+    entries: Vec<&'a LogEntry>,
+    hosts: HashSet<String>,
+}
+
+impl<'a> Builder<'a> {
+    fn new(entry: &'a LogEntry) -> Self {
+        let mut builder = Self {
+            entries: Vec::new(),
+            hosts: HashSet::new(),
+        };
+        builder.push(entry);
+        builder
+    }
+
+    fn push(&mut self, entry: &'a LogEntry) {
+        self.hosts.extend(host_of(&entry.url));
+        self.entries.push(entry);
+    }
+
+    fn last_ts(&self) -> DateTime<Utc> {
+        self.entries.last().map_or_else(Utc::now, |e| e.ts)
+    }
+
+    /// Whether `entry` continues this session's browsing: same host, or
+    /// navigated here from a page on one of its hosts.
+    fn is_related(&self, entry: &LogEntry) -> bool {
+        host_of(&entry.url).is_some_and(|h| self.hosts.contains(&h))
+            || entry
+                .referer
+                .as_deref()
+                .and_then(host_of)
+                .is_some_and(|h| self.hosts.contains(&h))
+    }
+
+    fn accepts(&self, entry: &LogEntry) -> bool {
+        let gap = entry.ts - self.last_ts();
+        if gap > CDuration::minutes(IDLE_GAP_MINUTES) {
+            return false;
+        }
+        self.is_related(entry) || gap <= CDuration::minutes(TOPIC_SWITCH_GAP_MINUTES)
+    }
+}
+
+/// The most requested host of a session; ties go to the host seen first.
+pub fn primary_domain(entries: &[&LogEntry]) -> String {
+    let mut counts: HashMap<String, usize> = HashMap::new();
+    let mut order = Vec::new();
+    for host in entries.iter().filter_map(|e| host_of(&e.url)) {
+        let count = counts.entry(host.clone()).or_insert(0);
+        if *count == 0 {
+            order.push(host);
+        }
+        *count += 1;
+    }
+    order
+        .iter()
+        .enumerate()
+        .max_by_key(|(i, host)| (counts[*host], std::cmp::Reverse(*i)))
+        .map(|(_, host)| host.clone())
+        .unwrap_or_default()
+}
+
+fn summarize(entries: &[&LogEntry], rollup_depth: Option<usize>) -> Session {
+    let page_entries: Vec<&LogEntry> = entries
+        .iter()
+        .copied()
+        .filter(|e| is_page_view(&e.url))
+        .collect();
+    let visits = page_entries.iter().enumerate().map(|(i, e)| Visit {
+        url: e.url.clone(),
+        ts: e.ts,
+        dwell: page_entries.get(i + 1).map(|next| next.ts - e.ts),
+    });
+    let pages = aggregate_visits(visits, rollup_depth);
+
+    Session {
+        start: entries.first().map_or_else(Utc::now, |e| e.ts),
+        end: entries.last().map_or_else(Utc::now, |e| e.ts),
+        primary_domain: primary_domain(entries),
+        requests: entries.len(),
+        pages,
+    }
+}
+
+/// Whether `url` looks like a page rather than a static asset.
+pub fn is_page_view(url: &str) -> bool {
+    let path = url
+        .split(['?', '#'])
+        .next()
+        .unwrap_or(url)
+        .to_ascii_lowercase();
+    !ASSET_EXTENSIONS.iter().any(|ext| path.ends_with(ext))
+}
+
+/// Splits log entries into browsing sessions using idle gaps and host/referer
+/// continuity, each sorted by time. Entries don't need to be sorted.
+pub fn split_sessions(entries: &[LogEntry]) -> Vec<Vec<&LogEntry>> {
+    let mut sorted: Vec<&LogEntry> = entries.iter().filter(|e| !e.is_marker()).collect();
+    sorted.sort_by_key(|e| e.ts);
+
+    let mut sessions = Vec::new();
+    let mut current: Option<Builder> = None;
+    for entry in sorted {
+        match current.as_mut() {
+            Some(builder) if builder.accepts(entry) => builder.push(entry),
+            _ => {
+                if let Some(done) = current.replace(Builder::new(entry)) {
+                    sessions.push(done.entries);
+                }
+            }
+        }
+    }
+    sessions.extend(current.map(|builder| builder.entries));
+    sessions
+}
+
+/// Groups log entries into browsing sessions with their page views.
+pub fn sessionize(entries: &[LogEntry], rollup_depth: Option<usize>) -> Vec<Session> {
+    split_sessions(entries)
+        .iter()
+        .map(|entries| summarize(entries, rollup_depth))
+        .collect()
+}
+
+pub fn format_duration(d: CDuration) -> String {
+    let secs = d.num_seconds().max(0);
+    if secs < 60 {
+        format!("{secs}s")
+    } else if secs < 3600 {
+        format!("{}m {}s", secs / 60, secs % 60)
+    } else {
+        format!("{}h {}m", secs / 3600, (secs % 3600) / 60)
+    }
+}
+
+impl Session {
+    /// Renders the session as a compact text block for the LLM prompt.
+    pub fn render(&self) -> String {
+        let start = self.start.with_timezone(&Local);
+        let end = self.end.with_timezone(&Local);
+        let mut lines = vec![format!(
+            "Session {} - {} ({}, {} requests, primary domain: {})",
+            start.format("%Y-%m-%d %H:%M"),
+            end.format("%H:%M"),
+            format_duration(self.end - self.start),
+            self.requests,
+            self.primary_domain
+        )];
+        for page in &self.pages {
+            let first = page.first.with_timezone(&Local).format("%H:%M:%S");
+            let mut line = format!("  {first} {}", page.url);
+            if page.count > 1 {
+                let last = page.last.with_timezone(&Local).format("%H:%M:%S");
+                let _ = write!(line, " ×{} (last seen {last})", page.count);
+            }
+            if let Some(dwell) = page.dwell {
+                let _ = write!(line, " (dwell ~{})", format_duration(dwell));
+            }
+            lines.push(line);
+        }
+        lines.join("\n")
+    }
+}
+
+fn render_marker(event: CaptureEvent, ts: DateTime<Utc>) -> String {
+    let at = ts.with_timezone(&Local).format("%Y-%m-%d %H:%M");
+    match event {
+        CaptureEvent::Pause => {
+            format!(
+                "Capture paused at {at} by the user; browsing until it resumed was not recorded"
+            )
+        }
+        CaptureEvent::Resume => format!("Capture resumed at {at}"),
+    }
+}
+
+/// Renders the sessions of `entries` for `summarize_with_llm`, with the
+/// pause/resume markers of `entries` in between so gaps aren't read as idle time.
+pub fn render_sessions(sessions: &[Session], entries: &[LogEntry]) -> Vec<String> {
+    let mut blocks: Vec<(DateTime<Utc>, String)> =
+        sessions.iter().map(|s| (s.start, s.render())).collect();
+    blocks.extend(
+        entries
+            .iter()
+            .filter_map(|e| Some((e.ts, render_marker(e.event?, e.ts)))),
+    );
+    blocks.sort_by_key(|(ts, _)| *ts);
+    blocks.into_iter().map(|(_, block)| block).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(url: &str, minutes: i64) -> LogEntry {
+        LogEntry::new(
+            url.to_string(),
+            DateTime::from_timestamp(1_700_000_000, 0).unwrap_or_default()
+                + CDuration::minutes(minutes),
+        )
+    }
+
+    fn urls<'a>(sessions: &[Vec<&'a LogEntry>]) -> Vec<Vec<&'a str>> {
+        sessions
+            .iter()
+            .map(|s| s.iter().map(|e| e.url.as_str()).collect())
+            .collect()
+    }
+
+    #[test]
+    fn splits_on_idle_gaps_and_topic_switches() {
+        let entries = vec![
+            at("https://a.example/1", 0),
+            // Unrelated, but right after: same session
+            at("https://b.example/1", 2),
+            // Same host after a longer pause: same session
+            at("https://a.example/2", 12),
+            // Unrelated after a pause: new session
+            at("https://c.example/1", 20),
+            // Same host after an idle gap: new session
+            at("https://c.example/2", 20 + IDLE_GAP_MINUTES + 1),
+        ];
+        assert_eq!(
+            urls(&split_sessions(&entries)),
+            [
+                vec![
+                    "https://a.example/1",
+                    "https://b.example/1",
+                    "https://a.example/2"
+                ],
+                vec!["https://c.example/1"],
+                vec!["https://c.example/2"],
+            ]
+        );
+    }
+
+    #[test]
+    fn follows_referers_and_sorts_entries() {
+        let linked = LogEntry {
+            referer: Some("https://a.example/post".to_string()),
+            ..at("https://other.example/", 10)
+        };
+        let entries = vec![linked, at("https://a.example/post", 0)];
+        assert_eq!(
+            urls(&split_sessions(&entries)),
+            [vec!["https://a.example/post", "https://other.example/"]]
+        );
+    }
+
+    #[test]
+    fn ignores_markers_and_handles_empty_input() {
+        assert!(split_sessions(&[]).is_empty());
+        let entries = vec![
+            LogEntry::marker(CaptureEvent::Pause),
+            at("https://a.example/", 0),
+            LogEntry::marker(CaptureEvent::Resume),
+        ];
+        assert_eq!(
+            urls(&split_sessions(&entries)),
+            [vec!["https://a.example/"]]
+        );
+        assert!(split_sessions(&entries[..1]).is_empty());
+    }
+}
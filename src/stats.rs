@@ -0,0 +1,328 @@
+use anyhow::Result;
+use chrono::{DateTime, Datelike, Local, NaiveDate, Timelike, Utc};
+use clap::Args;
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt::Write as _,
+};
+
+use crate::{parse_since, read_log_since, rules::host_of, storage, LogEntry};
+
+// ------------ stats --------------------------------------------------------
+const WEEKDAYS: [&str; 7] = ["Mon", "Tue", "Wed", "Thu", "Fri", "Sat", "Sun"];
+const SHADES: [&str; 5] = ["  ", "░░", "▒▒", "▓▓", "██"];
+const BAR_WIDTH: usize = 40;
+
+#[derive(Args)]
+pub struct StatsArgs {
+    /// Start of the window (<duration> ago or RFC 3339 time)
+    #[arg(short, long, default_value = "7d")]
+    since: String,
+    /// End of the window (<duration> ago or RFC 3339 time)
+    #[arg(short, long)]
+    until: Option<String>,
+    /// Rows shown per table (the JSON lists every new and erroring domain)
+    #[arg(long, default_value_t = 10)]
+    top: usize,
+    /// Print the stats as JSON
+    #[arg(long)]
+    json: bool,
+}
+
+#[derive(Clone, Serialize)]
+struct DomainStats {
+    domain: String,
+    requests: usize,
+    bytes: u64,
+    /// Requests with a recorded status code
+    responses: usize,
+    /// Responses with status 400 or above
+    errors: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error_rate: Option<f64>,
+}
+
+#[derive(Serialize)]
+struct NewDomain {
+    domain: String,
+    first_seen: DateTime<Utc>,
+    requests: usize,
+}
+
+#[derive(Serialize)]
+struct DayCount {
+    date: NaiveDate,
+    requests: usize,
+}
+
+#[derive(Serialize)]
+struct Stats {
+    since: DateTime<Utc>,
+    until: DateTime<Utc>,
+    requests: usize,
+    bytes: u64,
+    domains: usize,
+    top_domains: Vec<DomainStats>,
+    /// Hosts with at least one error response, most errors first
+    errors: Vec<DomainStats>,
+    new_domains: Vec<NewDomain>,
+    days: Vec<DayCount>,
+    /// Requests by local weekday (Monday first) and hour
+    heatmap: [[usize; 24]; 7],
+}
+
+fn domain_stats(entries: &[&LogEntry]) -> Vec<DomainStats> {
+    let mut domains: HashMap<String, DomainStats> = HashMap::new();
+    for entry in entries {
+        let Some(host) = host_of(&entry.url) else {
+            continue;
+        };
+        let stats = domains.entry(host.clone()).or_insert_with(|| DomainStats {
+            domain: host,
+            requests: 0,
+            bytes: 0,
+            responses: 0,
+            errors: 0,
+            error_rate: None,
+        });
+        stats.requests += 1;
+        stats.bytes += entry.bytes.unwrap_or(0);
+        if let Some(status) = entry.status {
+            stats.responses += 1;
+            if status >= 400 {
+                stats.errors += 1;
+            }
+        }
+    }
+    domains
+        .into_values()
+        .map(|mut stats| {
+            stats.error_rate =
+                (stats.responses > 0).then(|| stats.errors as f64 / stats.responses as f64);
+            stats
+        })
+        .collect()
+}
+
+/// Hosts first requested inside the window. Hosts that only survive in the
+/// daily aggregates of `gc` still count as seen before.
+fn new_domains(
+    before: &[&LogEntry],
+    entries: &[&LogEntry],
+    since: DateTime<Utc>,
+) -> Result<Vec<NewDomain>> {
+    let since_day = since.with_timezone(&Local).date_naive();
+    let mut known: HashSet<String> = storage::read_daily()?
+        .into_values()
+        .filter(|day| day.date < since_day)
+        .flat_map(|day| day.domains.into_keys())
+        .collect();
+    known.extend(before.iter().filter_map(|e| host_of(&e.url)));
+
+    let mut found: HashMap<String, NewDomain> = HashMap::new();
+    for entry in entries {
+        let Some(host) = host_of(&entry.url).filter(|h| !known.contains(h)) else {
+            continue;
+        };
+        let domain = found.entry(host.clone()).or_insert_with(|| NewDomain {
+            domain: host,
+            first_seen: entry.ts,
+            requests: 0,
+        });
+        domain.requests += 1;
+    }
+    let mut found: Vec<NewDomain> = found.into_values().collect();
+    found.sort_by_key(|d| d.first_seen);
+    Ok(found)
+}
+
+fn compute(
+    log: &[LogEntry],
+    since: DateTime<Utc>,
+    until: DateTime<Utc>,
+    top: usize,
+) -> Result<Stats> {
+    let (before, mut entries): (Vec<&LogEntry>, Vec<&LogEntry>) = log
+        .iter()
+        .filter(|e| !e.is_marker() && e.ts < until)
+        .partition(|e| e.ts < since);
+    entries.sort_by_key(|e| e.ts);
+
+    let mut days: BTreeMap<NaiveDate, usize> = BTreeMap::new();
+    let mut heatmap = [[0; 24]; 7];
+    for entry in &entries {
+        let local = entry.ts.with_timezone(&Local);
+        *days.entry(local.date_naive()).or_default() += 1;
+        heatmap[local.weekday().num_days_from_monday() as usize][local.hour() as usize] += 1;
+    }
+
+    let mut domains = domain_stats(&entries);
+    let domain_count = domains.len();
+    domains.sort_by(|a, b| {
+        b.requests
+            .cmp(&a.requests)
+            .then_with(|| a.domain.cmp(&b.domain))
+    });
+    let mut errors: Vec<DomainStats> = domains.iter().filter(|d| d.errors > 0).cloned().collect();
+    errors.sort_by(|a, b| {
+        b.errors
+            .cmp(&a.errors)
+            .then_with(|| a.domain.cmp(&b.domain))
+    });
+    domains.truncate(top);
+
+    Ok(Stats {
+        since,
+        until,
+        requests: entries.len(),
+        bytes: entries.iter().filter_map(|e| e.bytes).sum(),
+        domains: domain_count,
+        top_domains: domains,
+        errors,
+        new_domains: new_domains(&before, &entries, since)?,
+        days: days
+            .into_iter()
+            .map(|(date, requests)| DayCount { date, requests })
+            .collect(),
+        heatmap,
+    })
+}
+
+fn human_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KB", "MB", "GB", "TB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{bytes} B")
+    } else {
+        format!("{value:.1} {}", UNITS[unit])
+    }
+}
+
+fn percent(rate: Option<f64>) -> String {
+    rate.map_or_else(|| "-".to_string(), |r| format!("{:.1}%", r * 100.0))
+}
+
+fn print_domains(title: &str, domains: &[DomainStats], top: usize) {
+    println!("{title}");
+    println!(
+        "  {:<40} {:>9} {:>10} {:>7} {:>10}",
+        "Domain", "Requests", "Bytes", "Errors", "Error rate"
+    );
+    for d in domains.iter().take(top) {
+        println!(
+            "  {:<40} {:>9} {:>10} {:>7} {:>10}",
+            d.domain,
+            d.requests,
+            human_bytes(d.bytes),
+            d.errors,
+            percent(d.error_rate)
+        );
+    }
+    if domains.len() > top {
+        println!("  ... and {} more", domains.len() - top);
+    }
+    println!();
+}
+
+fn print_heatmap(heatmap: &[[usize; 24]; 7]) {
+    let max = heatmap.iter().flatten().copied().max().unwrap_or(0).max(1);
+    println!("Requests by hour (local time)");
+    let header = (0..24).step_by(3).fold(String::new(), |mut out, hour| {
+        let _ = write!(out, "{hour:<6}");
+        out
+    });
+    println!("      {}", header.trim_end());
+    for (day, hours) in WEEKDAYS.iter().zip(heatmap) {
+        let row: String = hours
+            .iter()
+            .map(|&n| SHADES[(n * (SHADES.len() - 1)).div_ceil(max)])
+            .collect();
+        println!("  {day} {row}");
+    }
+    println!();
+}
+
+fn print_stats(stats: &Stats, top: usize) {
+    let local = |ts: DateTime<Utc>| ts.with_timezone(&Local).format("%Y-%m-%d %H:%M");
+    println!(
+        "{} – {}: {} requests to {} domains, {}",
+        local(stats.since),
+        local(stats.until),
+        stats.requests,
+        stats.domains,
+        human_bytes(stats.bytes)
+    );
+    println!();
+    if stats.requests == 0 {
+        return;
+    }
+
+    print_domains("Top domains", &stats.top_domains, top);
+
+    println!("Requests per day");
+    let max = stats
+        .days
+        .iter()
+        .map(|d| d.requests)
+        .max()
+        .unwrap_or(0)
+        .max(1);
+    for day in &stats.days {
+        let bar = "█".repeat((day.requests * BAR_WIDTH).div_ceil(max));
+        println!(
+            "  {} {}  {bar} {}",
+            day.date,
+            WEEKDAYS[day.date.weekday().num_days_from_monday() as usize],
+            day.requests
+        );
+    }
+    println!();
+
+    print_heatmap(&stats.heatmap);
+
+    println!("New domains ({})", stats.new_domains.len());
+    for d in stats.new_domains.iter().take(top) {
+        println!(
+            "  {:<40} {}  {} requests",
+            d.domain,
+            local(d.first_seen),
+            d.requests
+        );
+    }
+    if stats.new_domains.len() > top {
+        println!("  ... and {} more", stats.new_domains.len() - top);
+    }
+    println!();
+
+    if stats.errors.is_empty() {
+        println!("No error responses");
+    } else {
+        print_domains("Error responses (status 400+)", &stats.errors, top);
+    }
+}
+
+/// Prints traffic statistics for a window of the log without calling the LLM.
+pub fn run_stats(args: &StatsArgs) -> Result<()> {
+    let since = parse_since(&args.since)?;
+    let until = args
+        .until
+        .as_deref()
+        .map(parse_since)
+        .transpose()?
+        .unwrap_or_else(Utc::now);
+    // The whole log, so new domains can be told apart from ones seen before
+    let log = read_log_since(None)?;
+    let stats = compute(&log, since, until, args.top)?;
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&stats)?);
+    } else {
+        print_stats(&stats, args.top);
+    }
+    Ok(())
+}
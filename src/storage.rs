@@ -0,0 +1,581 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Duration as CDuration, Local, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fs,
+    io::{BufRead, BufReader, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    aggregate::normalize_url, crypto, data_dir, log_path, memory, rules::host_of, LogEntry,
+};
+
+// ------------ segments & retention ---------------------------------------
+const SEGMENTS_DIR: &str = "segments";
+const DAILY_FILE: &str = "daily.ndjson";
+/// Aggregates staged by `gc` until the raw entries they count are dropped.
+const PENDING_DAILY_FILE: &str = "daily.pending.ndjson";
+/// Held while the active log or the page cache is appended to or replaced.
+const APPEND_LOCK_FILE: &str = "append.lock";
+
+/// Per-day request counts kept after raw entries expire.
+#[derive(Default, Serialize, Deserialize)]
+pub struct DailyAggregate {
+    /// Local calendar day, as in `export` and `stats`
+    pub date: NaiveDate,
+    pub requests: usize,
+    pub domains: BTreeMap<String, usize>,
+}
+
+fn segments_dir() -> Result<PathBuf> {
+    let d = data_dir()?.join(SEGMENTS_DIR);
+    fs::create_dir_all(&d)?;
+    Ok(d)
+}
+
+fn month_key(ts: DateTime<Utc>) -> String {
+    ts.format("%Y-%m").to_string()
+}
+
+/// The `YYYY-MM` month a segment file holds, from its `log-YYYY-MM.ndjson` name.
+fn segment_month(path: &Path) -> Option<String> {
+    let name = path.file_name()?.to_str()?;
+    let month = name.strip_prefix("log-")?.strip_suffix(".ndjson")?;
+    NaiveDate::parse_from_str(&format!("{month}-01"), "%Y-%m-%d").ok()?;
+    Some(month.to_string())
+}
+
+fn segment_files() -> Result<Vec<(String, PathBuf)>> {
+    let mut segments: Vec<(String, PathBuf)> = fs::read_dir(segments_dir()?)?
+        .filter_map(Result::ok)
+        .map(|e| e.path())
+        .filter_map(|p| Some((segment_month(&p)?, p)))
+        .collect();
+    segments.sort();
+    Ok(segments)
+}
+
+/// Log files that may hold entries at or after `since`, oldest first: the
+/// monthly segments from `since`'s month onwards, then the active log.
+/// Older segments are skipped without being opened.
+pub fn log_files_since(since: Option<DateTime<Utc>>) -> Result<Vec<PathBuf>> {
+    let first_month = since.map(month_key);
+    let mut files: Vec<PathBuf> = segment_files()?
+        .into_iter()
+        .filter(|(month, _)| first_month.as_ref().is_none_or(|first| month >= first))
+        .map(|(_, path)| path)
+        .collect();
+    let active = log_path()?;
+    if active.exists() {
+        files.push(active);
+    }
+    Ok(files)
+}
+
+/// Writes `data` to a temp file and renames it over `path`, as `SummaryState::save` does.
+pub fn write_atomic(path: &Path, data: &[u8]) -> Result<()> {
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, data).with_context(|| format!("Failed to write {}", tmp.display()))?;
+    fs::rename(&tmp, path).with_context(|| format!("Failed to replace {}", path.display()))?;
+    Ok(())
+}
+
+/// Makes `path` readable only by its owner; a no-op off Unix.
+#[cfg(unix)]
+pub fn restrict_permissions(path: &Path) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(path, fs::Permissions::from_mode(0o600))
+        .with_context(|| format!("Failed to restrict permissions of {}", path.display()))
+}
+
+#[cfg(not(unix))]
+pub fn restrict_permissions(_path: &Path) -> Result<()> {
+    Ok(())
+}
+
+/// Creates `path` empty and readable only by its owner, replacing any existing
+/// file, so a secret written into it is never briefly readable by others.
+pub fn create_private(path: &Path) -> Result<fs::File> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+            return Err(e).with_context(|| format!("Failed to replace {}", path.display()));
+        }
+        _ => {}
+    }
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options
+        .open(path)
+        .with_context(|| format!("Failed to create {}", path.display()))
+}
+
+fn to_ndjson<T: Serialize>(records: &[T]) -> Result<Vec<u8>> {
+    let mut out = Vec::new();
+    for record in records {
+        out.extend_from_slice(crypto::encode(record)?.as_bytes());
+        out.push(b'\n');
+    }
+    Ok(out)
+}
+
+/// The daily aggregates of a `gc` run that hadn't finished dropping raw entries.
+#[derive(Serialize, Deserialize)]
+struct PendingRollup {
+    /// Every expired entry before this time is counted in `days`
+    through: DateTime<Utc>,
+    days: Vec<DailyAggregate>,
+}
+
+fn read_pending_rollup() -> Result<Option<PendingRollup>> {
+    match fs::read_to_string(data_dir()?.join(PENDING_DAILY_FILE)) {
+        Ok(text) => Ok(Some(
+            crypto::decode(text.trim()).context("Corrupt pending daily aggregates")?,
+        )),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Locks the append-only files (the active log and the page cache) against
+/// concurrent appends and replacement, across processes. The lock is
+/// released when the returned file is dropped.
+pub fn lock_appends() -> Result<fs::File> {
+    let file = fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(data_dir()?.join(APPEND_LOCK_FILE))?;
+    file.lock().context("Failed to lock the data directory")?;
+    Ok(file)
+}
+
+pub fn read_daily() -> Result<BTreeMap<NaiveDate, DailyAggregate>> {
+    let path = data_dir()?.join(DAILY_FILE);
+    let Ok(file) = fs::File::open(path) else {
+        return Ok(BTreeMap::new());
+    };
+    Ok(BufReader::new(file)
+        .lines()
+        .map_while(Result::ok)
+        .filter_map(|line| crypto::decode::<DailyAggregate>(&line).ok())
+        .map(|day| (day.date, day))
+        .collect())
+}
+
+/// Like `read_daily`, but fails on a record it can't decode instead of
+/// skipping it, for `gc`, which rewrites the file.
+fn read_daily_strict() -> Result<BTreeMap<NaiveDate, DailyAggregate>> {
+    let path = data_dir()?.join(DAILY_FILE);
+    let text = match fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(BTreeMap::new()),
+        Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+    };
+    let mut daily = BTreeMap::new();
+    for (n, line) in text
+        .lines()
+        .enumerate()
+        .filter(|(_, l)| !l.trim().is_empty())
+    {
+        let day: DailyAggregate = crypto::decode(line)
+            .with_context(|| format!("Can't decode line {} of {}", n + 1, path.display()))?;
+        daily.insert(day.date, day);
+    }
+    Ok(daily)
+}
+
+/// Parsed entries plus lines that decrypt but don't parse, which stay in
+/// their file verbatim.
+#[derive(Default)]
+struct Contents {
+    entries: Vec<LogEntry>,
+    unparsed: Vec<String>,
+}
+
+/// What identifies a log entry when an interrupted `gc` left it in two files.
+type EntryKey = (DateTime<Utc>, String, Option<String>);
+
+fn entry_key(entry: &LogEntry) -> EntryKey {
+    (entry.ts, entry.url.clone(), entry.client.clone())
+}
+
+/// Reads one log file, dropping entries in `moved` and returning how many
+/// were dropped. A line that can't be decrypted fails the read, since
+/// rewriting the file without it would lose it.
+fn read_log_file(reader: impl BufRead, moved: &HashSet<EntryKey>) -> Result<(Contents, usize)> {
+    let mut contents = Contents::default();
+    let mut dropped = 0;
+    for (n, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let plain = crypto::open(&line).with_context(|| format!("Can't decrypt line {}", n + 1))?;
+        match serde_json::from_str::<LogEntry>(&plain) {
+            Ok(entry) if moved.contains(&entry_key(&entry)) => dropped += 1,
+            Ok(entry) => contents.entries.push(entry),
+            Err(_) => contents.unparsed.push(line),
+        }
+    }
+    Ok((contents, dropped))
+}
+
+/// Everything `gc` reads: entries from the segments and the active log, the
+/// unparsed lines of each segment by month and of the active log, and how
+/// much of the active log was read.
+struct Store {
+    entries: Vec<LogEntry>,
+    segment_unparsed: BTreeMap<String, Vec<String>>,
+    active_unparsed: Vec<String>,
+    active_len: u64,
+}
+
+/// Reads the segments, then the active log, skipping entries of the active
+/// log that an interrupted run already wrote to their segment.
+fn read_store(segments: &[(String, PathBuf)], active: &Path) -> Result<Store> {
+    let mut entries = Vec::new();
+    let mut segment_unparsed = BTreeMap::new();
+    for (month, path) in segments {
+        let (contents, _) = read_log_file(BufReader::new(fs::File::open(path)?), &HashSet::new())
+            .with_context(|| {
+            format!(
+                "Failed to read {}; fix or remove it before gc",
+                path.display()
+            )
+        })?;
+        entries.extend(contents.entries);
+        if !contents.unparsed.is_empty() {
+            segment_unparsed.insert(month.clone(), contents.unparsed);
+        }
+    }
+    // Remember how much of the active log we've read; the logger may keep
+    // appending while we work, and those lines are carried over at the end.
+    let (active_contents, moved, active_len) = match fs::File::open(active) {
+        Ok(file) => {
+            let len = file.metadata()?.len();
+            let in_segments: HashSet<EntryKey> = entries.iter().map(entry_key).collect();
+            let (contents, moved) = read_log_file(BufReader::new(file.take(len)), &in_segments)
+                .with_context(|| {
+                    format!(
+                        "Failed to read {}; fix or remove it before gc",
+                        active.display()
+                    )
+                })?;
+            (contents, moved, len)
+        }
+        Err(_) => (Contents::default(), 0, 0),
+    };
+    if moved > 0 {
+        println!("Dropping {moved} entries an interrupted gc already moved into segments");
+    }
+    entries.extend(active_contents.entries);
+    Ok(Store {
+        entries,
+        segment_unparsed,
+        active_unparsed: active_contents.unparsed,
+        active_len,
+    })
+}
+
+/// Adds expired entries to the daily aggregates, on top of the aggregates
+/// an interrupted run staged if there are any.
+fn roll_up(expired: &[LogEntry], cutoff: DateTime<Utc>) -> Result<PendingRollup> {
+    let (mut daily, counted_before) = match read_pending_rollup()? {
+        Some(pending) => {
+            println!("Finishing an interrupted gc");
+            let days = pending.days.into_iter().map(|d| (d.date, d)).collect();
+            (days, Some(pending.through))
+        }
+        None => (read_daily_strict()?, None),
+    };
+    for entry in expired
+        .iter()
+        .filter(|e| !e.is_marker() && counted_before.is_none_or(|t| e.ts >= t))
+    {
+        let date = entry.ts.with_timezone(&Local).date_naive();
+        let day = daily.entry(date).or_insert_with(|| DailyAggregate {
+            date,
+            ..DailyAggregate::default()
+        });
+        day.requests += 1;
+        if let Some(host) = host_of(&entry.url) {
+            *day.domains.entry(host).or_default() += 1;
+        }
+    }
+    Ok(PendingRollup {
+        through: counted_before.map_or(cutoff, |t| t.max(cutoff)),
+        days: daily.into_values().collect(),
+    })
+}
+
+/// Moves entries into monthly segments, rolls entries older than
+/// `keep_raw_days` into daily aggregates and drops them, rewriting every
+/// file atomically.
+///
+/// The new aggregates are staged before any raw entry is dropped and
+/// committed last. A run interrupted in between is finished by the next
+/// one, which doesn't count entries the staged aggregates already cover and
+/// drops the copies of entries it had already moved into segments.
+pub fn run_gc(keep_raw_days: u32, dry_run: bool) -> Result<()> {
+    crypto::require_key()?;
+    let active = log_path()?;
+    let segments = segment_files()?;
+    let store = read_store(&segments, &active)?;
+
+    let cutoff = Utc::now() - CDuration::days(i64::from(keep_raw_days));
+    let (expired, mut kept): (Vec<LogEntry>, Vec<LogEntry>) =
+        store.entries.into_iter().partition(|e| e.ts < cutoff);
+    kept.sort_by_key(|e| e.ts);
+
+    let current_month = month_key(Utc::now());
+    let mut months: BTreeMap<String, Vec<&LogEntry>> = store
+        .segment_unparsed
+        .keys()
+        .map(|month| (month.clone(), Vec::new()))
+        .collect();
+    let mut active_entries = Vec::new();
+    for entry in &kept {
+        let month = month_key(entry.ts);
+        if month < current_month {
+            months.entry(month).or_default().push(entry);
+        } else {
+            active_entries.push(entry);
+        }
+    }
+
+    println!(
+        "{} entries older than {keep_raw_days} days roll up into daily aggregates; \
+         {} kept in {} monthly segments and {} in the active log",
+        expired.len(),
+        kept.len() - active_entries.len(),
+        months.len(),
+        active_entries.len()
+    );
+    let unparsed =
+        store.active_unparsed.len() + store.segment_unparsed.values().map(Vec::len).sum::<usize>();
+    if unparsed > 0 {
+        println!("{unparsed} unparseable lines are kept in the files they're in");
+    }
+    if dry_run {
+        return Ok(());
+    }
+
+    let pending = roll_up(&expired, cutoff)?;
+    let pending_path = data_dir()?.join(PENDING_DAILY_FILE);
+    write_atomic(&pending_path, crypto::encode(&pending)?.as_bytes())?;
+
+    let dir = segments_dir()?;
+    for (month, entries) in &months {
+        let mut data = to_ndjson(entries)?;
+        for line in store.segment_unparsed.get(month).into_iter().flatten() {
+            data.extend_from_slice(line.as_bytes());
+            data.push(b'\n');
+        }
+        write_atomic(&dir.join(format!("log-{month}.ndjson")), &data)?;
+    }
+    for (month, path) in &segments {
+        if !months.contains_key(month) {
+            fs::remove_file(path)?;
+        }
+    }
+
+    let mut data = to_ndjson(&active_entries)?;
+    for line in &store.active_unparsed {
+        data.extend_from_slice(line.as_bytes());
+        data.push(b'\n');
+    }
+    {
+        let _lock = lock_appends()?;
+        if let Ok(mut file) = fs::File::open(&active) {
+            file.seek(SeekFrom::Start(store.active_len))?;
+            file.read_to_end(&mut data)?;
+        }
+        write_atomic(&active, &data)?;
+    }
+
+    write_atomic(&data_dir()?.join(DAILY_FILE), &to_ndjson(&pending.days)?)?;
+    fs::remove_file(&pending_path)?;
+
+    compact_pages()?;
+    memory::compact()?;
+    println!("Compaction complete");
+    Ok(())
+}
+
+// ------------ page cache ---------------------------------------------------
+const PAGES_FILE: &str = "pages.ndjson";
+/// Longest page text kept per fetch, in characters.
+const MAX_PAGE_CHARS: usize = 20_000;
+
+/// Text of a page fetched for the summarizer, kept so `search` can match it.
+#[derive(Serialize, Deserialize)]
+struct CachedPage {
+    url: String,
+    fetched: DateTime<Utc>,
+    text: String,
+}
+
+/// Appends the text fetched for `url` to the page cache.
+pub fn cache_page(url: &str, text: &str) -> Result<()> {
+    let text = match text.char_indices().nth(MAX_PAGE_CHARS) {
+        Some((end, _)) => &text[..end],
+        None => text,
+    };
+    let page = CachedPage {
+        url: url.to_string(),
+        fetched: Utc::now(),
+        text: text.to_string(),
+    };
+    let line = crypto::encode(&page)?;
+    let _lock = lock_appends()?;
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(data_dir()?.join(PAGES_FILE))?;
+    writeln!(file, "{line}")?;
+    Ok(())
+}
+
+/// Keeps only the latest fetch of each page (by normalized URL) in the page
+/// cache. Lines that don't parse are kept verbatim.
+fn compact_pages() -> Result<()> {
+    let path = data_dir()?.join(PAGES_FILE);
+    let _lock = lock_appends()?;
+    let text = match fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+    };
+    let mut latest: HashMap<String, (DateTime<Utc>, &str)> = HashMap::new();
+    let mut unparsed = Vec::new();
+    let mut records = 0;
+    for line in text.lines().filter(|l| !l.trim().is_empty()) {
+        let Ok(page) = crypto::decode::<CachedPage>(line) else {
+            unparsed.push(line);
+            continue;
+        };
+        records += 1;
+        let key = normalize_url(&page.url);
+        if latest
+            .get(&key)
+            .is_none_or(|(fetched, _)| page.fetched >= *fetched)
+        {
+            latest.insert(key, (page.fetched, line));
+        }
+    }
+    if latest.len() == records {
+        return Ok(());
+    }
+
+    let mut kept: Vec<(DateTime<Utc>, &str)> = latest.into_values().collect();
+    kept.sort_by_key(|(fetched, _)| *fetched);
+    let mut data = String::new();
+    for line in kept.iter().map(|(_, line)| *line).chain(unparsed) {
+        data.push_str(line);
+        data.push('\n');
+    }
+    write_atomic(&path, data.as_bytes())?;
+    println!(
+        "Dropped {} older copies of cached pages",
+        records - kept.len()
+    );
+    Ok(())
+}
+
+/// Cached page text keyed by normalized URL; the latest fetch wins.
+pub fn read_pages() -> Result<HashMap<String, String>> {
+    let Ok(file) = fs::File::open(data_dir()?.join(PAGES_FILE)) else {
+        return Ok(HashMap::new());
+    };
+    Ok(BufReader::new(file)
+        .lines()
+        .map_while(Result::ok)
+        .filter_map(|line| crypto::decode::<CachedPage>(&line).ok())
+        .map(|page| (normalize_url(&page.url), page.text))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(url: &str, day: u32) -> LogEntry {
+        let ts = format!("2026-08-{day:02}T10:00:00Z");
+        LogEntry::new(
+            url.to_string(),
+            DateTime::parse_from_rfc3339(&ts).map_or_else(|_| Utc::now(), |t| t.to_utc()),
+        )
+    }
+
+    fn lines(entries: &[LogEntry], extra: &str) -> String {
+        let mut out: String = entries
+            .iter()
+            .filter_map(|e| serde_json::to_string(e).ok())
+            .map(|line| line + "\n")
+            .collect();
+        out.push_str(extra);
+        out
+    }
+
+    #[test]
+    fn read_store_drops_copies_left_by_an_interrupted_gc() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("dtp-gc-test-{}", std::process::id()));
+        fs::create_dir_all(&dir)?;
+        let (a, b, c) = (
+            entry("https://a.example/", 1),
+            entry("https://b.example/", 2),
+            entry("https://c.example/", 3),
+        );
+        // The crashed run had written the segment but not swapped the active log
+        let segment = dir.join("log-2026-08.ndjson");
+        fs::write(&segment, lines(&[a.clone(), b.clone()], "{not json\n"))?;
+        let active = dir.join("log.ndjson");
+        fs::write(&active, lines(&[a, b, c], "\n"))?;
+
+        let store = read_store(&[("2026-08".to_string(), segment)], &active);
+        fs::remove_dir_all(&dir)?;
+        let store = store?;
+        let urls: Vec<&str> = store.entries.iter().map(|e| e.url.as_str()).collect();
+        assert_eq!(
+            urls,
+            [
+                "https://a.example/",
+                "https://b.example/",
+                "https://c.example/"
+            ]
+        );
+        assert_eq!(
+            store.segment_unparsed.get("2026-08").map(Vec::as_slice),
+            Some(["{not json".to_string()].as_slice())
+        );
+        assert!(store.active_unparsed.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn read_log_file_keeps_other_clients_and_garbage() -> Result<()> {
+        let a = entry("https://a.example/", 1);
+        let other_client = LogEntry {
+            client: Some("10.0.0.2".to_string()),
+            ..a.clone()
+        };
+        let moved: HashSet<EntryKey> = [entry_key(&a)].into();
+        let text = lines(&[a, other_client], "garbage\n");
+        let (contents, dropped) = read_log_file(text.as_bytes(), &moved)?;
+        assert_eq!(dropped, 1);
+        assert_eq!(contents.entries.len(), 1);
+        assert_eq!(contents.unparsed, ["garbage"]);
+        Ok(())
+    }
+
+    #[test]
+    fn read_log_file_refuses_records_it_cannot_decrypt() {
+        let text = "{\"url\":\"https://a.example/\",\"ts\":\"2026-08-01T10:00:00Z\"}\nenc1:AAAA\n";
+        let err = read_log_file(text.as_bytes(), &HashSet::new()).err();
+        assert!(err.is_some_and(|e| e.to_string().contains("line 2")));
+    }
+}
@@ -0,0 +1,267 @@
+use anyhow::{Context, Result};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use crate::{data_dir, rules::DomainRules, storage::create_private, PROXY_PORT};
+
+// ------------ local CA -----------------------------------------------------
+const CA_DIR: &str = "ca";
+const CA_CERT_FILE: &str = "ca.pem";
+const CA_KEY_FILE: &str = "ca.key";
+const CERT_DB_DIR: &str = "ssl_db";
+const CA_SUBJECT: &str = "/CN=Digital Twin Proxy Local CA/O=digital-twin-proxy";
+
+pub struct LocalCa {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+    pub cert_db: PathBuf,
+}
+
+impl LocalCa {
+    fn at(dir: &Path) -> Self {
+        Self {
+            cert: dir.join(CA_CERT_FILE),
+            key: dir.join(CA_KEY_FILE),
+            cert_db: dir.join(CERT_DB_DIR),
+        }
+    }
+
+    fn paths() -> Result<Self> {
+        let dir = data_dir()?.join(CA_DIR);
+        fs::create_dir_all(&dir)?;
+        Ok(Self::at(&dir))
+    }
+
+    /// The CA if it was generated already, without creating anything.
+    pub fn existing() -> Result<Option<Self>> {
+        let ca = Self::at(&data_dir()?.join(CA_DIR));
+        Ok((ca.cert.exists() && ca.key.exists()).then_some(ca))
+    }
+
+    /// Returns the CA in the data dir, generating it with `openssl` on first use.
+    pub fn ensure() -> Result<Self> {
+        let ca = Self::paths()?;
+        if !ca.cert.exists() || !ca.key.exists() {
+            ca.generate()?;
+        }
+        Ok(ca)
+    }
+
+    /// Generates a fresh CA, replacing any existing one. Browsers that trusted
+    /// the previous certificate need to import the new one.
+    pub fn regenerate() -> Result<Self> {
+        let ca = Self::paths()?;
+        if ca.cert_db.exists() {
+            // Host certificates in the db were signed by the old CA
+            fs::remove_dir_all(&ca.cert_db).context("Failed to clear certificate cache")?;
+        }
+        ca.generate()?;
+        Ok(ca)
+    }
+
+    fn generate(&self) -> Result<()> {
+        println!("Generating local CA in {}...", self.cert.display());
+        // openssl keeps the mode of a file it overwrites
+        create_private(&self.key)?;
+        let output = Command::new("openssl")
+            .args(["req", "-x509", "-new", "-nodes", "-sha256"])
+            .args(["-newkey", "rsa:2048", "-days", "3650"])
+            .args(["-subj", CA_SUBJECT])
+            .args(["-addext", "basicConstraints=critical,CA:TRUE"])
+            .args(["-addext", "keyUsage=critical,keyCertSign,cRLSign"])
+            .arg("-keyout")
+            .arg(&self.key)
+            .arg("-out")
+            .arg(&self.cert)
+            .output()
+            .context("Failed to run openssl (is it installed?)")?;
+        if !output.status.success() {
+            anyhow::bail!(
+                "openssl failed to generate the CA:\n{}",
+                String::from_utf8_lossy(&output.stderr).trim_end()
+            );
+        }
+        Ok(())
+    }
+
+    pub fn fingerprint(&self) -> Result<String> {
+        let output = Command::new("openssl")
+            .args(["x509", "-noout", "-fingerprint", "-sha256", "-in"])
+            .arg(&self.cert)
+            .output()
+            .context("Failed to run openssl")?;
+        if !output.status.success() {
+            anyhow::bail!("Could not read {}", self.cert.display());
+        }
+        let text = String::from_utf8_lossy(&output.stdout);
+        Ok(text
+            .trim()
+            .split_once('=')
+            .map_or_else(|| text.trim().to_string(), |(_, fp)| fp.to_string()))
+    }
+
+    /// Initializes Squid's dynamic certificate database if it doesn't exist yet.
+    fn ensure_cert_db(&self, certgen: &Path) -> Result<()> {
+        if self.cert_db.exists() {
+            return Ok(());
+        }
+        let output = Command::new(certgen)
+            .arg("-c")
+            .arg("-s")
+            .arg(&self.cert_db)
+            .args(["-M", "4MB"])
+            .output()
+            .context("Failed to initialize the Squid certificate database")?;
+        if !output.status.success() {
+            anyhow::bail!(
+                "{} failed:\n{}",
+                certgen.display(),
+                String::from_utf8_lossy(&output.stderr).trim_end()
+            );
+        }
+        Ok(())
+    }
+}
+
+fn find_certgen_binary() -> Option<PathBuf> {
+    // Squid's certificate generator helper (named ssl_crtd before Squid 4)
+    let paths = [
+        "/usr/lib/squid/security_file_certgen",
+        "/usr/lib64/squid/security_file_certgen",
+        "/usr/libexec/squid/security_file_certgen",
+        "/usr/local/libexec/squid/security_file_certgen",
+        "/opt/homebrew/opt/squid/libexec/security_file_certgen",
+        "/usr/local/opt/squid/libexec/security_file_certgen",
+        "/usr/lib/squid/ssl_crtd",
+        "/usr/lib64/squid/ssl_crtd",
+    ];
+    paths
+        .iter()
+        .map(Path::new)
+        .find(|p| p.exists())
+        .map(Path::to_path_buf)
+}
+
+// ------------ squid config -------------------------------------------------
+/// Squid directives for SSL-bump mode: the replacement `http_port` line and
+/// the `ssl_bump` rules. Hosts excluded by the domain rules are spliced
+/// (tunnelled untouched), everything else is decrypted.
+pub struct BumpConfig {
+    pub http_port: String,
+    pub rules: String,
+}
+
+fn certgen_binary() -> Result<PathBuf> {
+    find_certgen_binary().ok_or_else(|| {
+        anyhow::anyhow!(
+            "TLS interception needs Squid's security_file_certgen helper \
+             (install a Squid build with SSL support, e.g. squid-openssl on Debian/Ubuntu)"
+        )
+    })
+}
+
+/// The bump directives, generating the CA and certificate database on first use.
+pub fn bump_config(domains: &DomainRules) -> Result<BumpConfig> {
+    let ca = LocalCa::ensure()?;
+    let certgen = certgen_binary()?;
+    ca.ensure_cert_db(&certgen)?;
+    Ok(render_bump_config(&ca, &certgen, domains))
+}
+
+/// Like `bump_config`, but fails instead of creating the CA or database,
+/// for checks that mustn't change anything.
+pub fn preview_bump_config(domains: &DomainRules) -> Result<BumpConfig> {
+    let ca = LocalCa::existing()?.ok_or_else(|| {
+        anyhow::anyhow!(
+            "the local CA doesn't exist yet; it is created the first time the proxy \
+             starts with TLS interception or on `ca show`"
+        )
+    })?;
+    Ok(render_bump_config(&ca, &certgen_binary()?, domains))
+}
+
+fn render_bump_config(ca: &LocalCa, certgen: &Path, domains: &DomainRules) -> BumpConfig {
+    let http_port = format!(
+        "http_port {PROXY_PORT} ssl-bump tls-cert={} tls-key={} \
+         generate-host-certificates=on dynamic_cert_mem_cache_size=4MB",
+        ca.cert.display(),
+        ca.key.display()
+    );
+
+    let mut rules = vec![
+        format!(
+            "sslcrtd_program {} -s {} -M 4MB",
+            certgen.display(),
+            ca.cert_db.display()
+        ),
+        "acl step1 at_step SslBump1".to_string(),
+        "ssl_bump peek step1".to_string(),
+    ];
+    let splice: Vec<String> = domains
+        .deny
+        .iter()
+        .chain(&domains.no_bump)
+        .map(|d| format!(".{d}"))
+        .collect();
+    if !splice.is_empty() {
+        rules.push(format!(
+            "acl dt_no_bump ssl::server_name {}",
+            splice.join(" ")
+        ));
+        rules.push("ssl_bump splice dt_no_bump".to_string());
+    }
+    if domains.allow.is_empty() {
+        rules.push("ssl_bump bump all".to_string());
+    } else {
+        let allow: Vec<String> = domains.allow.iter().map(|d| format!(".{d}")).collect();
+        rules.push(format!("acl dt_bump ssl::server_name {}", allow.join(" ")));
+        rules.push("ssl_bump bump dt_bump".to_string());
+        rules.push("ssl_bump splice all".to_string());
+    }
+
+    BumpConfig {
+        http_port,
+        rules: rules.join("\n"),
+    }
+}
+
+// ------------ commands -----------------------------------------------------
+pub fn run_ca_show() -> Result<()> {
+    let ca = LocalCa::ensure()?;
+    println!("CA certificate: {}", ca.cert.display());
+    println!("SHA-256 fingerprint: {}", ca.fingerprint()?);
+    println!();
+    println!("Import the certificate into your browser's trust store:");
+    println!("  Firefox:  Settings > Privacy & Security > Certificates > View Certificates >");
+    println!("            Authorities > Import, then tick \"Trust this CA to identify websites\"");
+    println!("  Chrome:   Settings > Privacy and security > Security > Manage certificates >");
+    println!("            Authorities > Import");
+
+    #[cfg(target_os = "linux")]
+    {
+        println!("  System:   sudo cp {} /usr/local/share/ca-certificates/digital-twin-proxy.crt && sudo update-ca-certificates", ca.cert.display());
+    }
+
+    #[cfg(target_os = "macos")]
+    {
+        println!("  System:   sudo security add-trusted-cert -d -r trustRoot -k /Library/Keychains/System.keychain {}", ca.cert.display());
+    }
+
+    println!();
+    println!(
+        "Keep {} private: anyone holding it can impersonate any website to this machine.",
+        ca.key.display()
+    );
+    Ok(())
+}
+
+pub fn run_ca_regenerate() -> Result<()> {
+    let ca = LocalCa::regenerate()?;
+    println!("New CA certificate: {}", ca.cert.display());
+    println!("SHA-256 fingerprint: {}", ca.fingerprint()?);
+    println!("Remove the old certificate from your browser and import the new one.");
+    Ok(())
+}
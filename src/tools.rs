@@ -0,0 +1,204 @@
+use anyhow::Result;
+use async_openai::types::{ChatCompletionTool, ChatCompletionToolType, FunctionObjectArgs};
+use chrono::{DateTime, Local, NaiveDate, Utc};
+use scraper::{Html, Selector};
+use std::{
+    collections::{BTreeSet, HashMap},
+    fmt::Write as _,
+};
+use tokio::time::Duration;
+
+use crate::{
+    aggregate::normalize_url,
+    parse_since, read_log_since, read_summary_history,
+    rules::{host_of, in_domain},
+    storage,
+};
+
+// ------------ agent tools --------------------------------------------------
+const TOP_PAGES: usize = 5;
+const MAX_SUMMARIES: usize = 10;
+const TITLE_TIMEOUT: Duration = Duration::from_secs(10);
+/// HTML read while looking for `</title>`; titles sit near the top.
+const TITLE_READ_LIMIT: usize = 64 * 1024;
+
+/// A function tool taking the given JSON-schema parameters.
+pub fn function(
+    name: &str,
+    description: &str,
+    parameters: serde_json::Value,
+) -> Result<ChatCompletionTool> {
+    Ok(ChatCompletionTool {
+        r#type: ChatCompletionToolType::Function,
+        function: FunctionObjectArgs::default()
+            .name(name)
+            .description(description)
+            .parameters(parameters)
+            .build()?,
+    })
+}
+
+fn local(ts: DateTime<Utc>) -> String {
+    ts.with_timezone(&Local)
+        .format("%Y-%m-%d %H:%M")
+        .to_string()
+}
+
+/// How often `host` (and its subdomains) was visited since `since`, from
+/// the raw log plus the daily aggregates `gc` keeps after entries expire.
+pub fn query_history(host: &str, since: Option<&str>) -> Result<String> {
+    let domain = host_of(host)
+        .unwrap_or_else(|| host.to_ascii_lowercase())
+        .trim_start_matches("www.")
+        .to_string();
+    let since = since.map(parse_since).transpose()?;
+    let log = read_log_since(since)?;
+    let entries: Vec<_> = log
+        .iter()
+        .filter(|e| !e.is_marker())
+        .filter(|e| host_of(&e.url).is_some_and(|h| in_domain(&h, &domain)))
+        .collect();
+
+    let mut out = String::new();
+    let window = since.map_or_else(String::new, |s| format!(" since {}", local(s)));
+    if let (Some(first), Some(last)) = (
+        entries.iter().map(|e| e.ts).min(),
+        entries.iter().map(|e| e.ts).max(),
+    ) {
+        let days: BTreeSet<NaiveDate> = entries
+            .iter()
+            .map(|e| e.ts.with_timezone(&Local).date_naive())
+            .collect();
+        let _ = writeln!(
+            out,
+            "{domain}{window}: {} requests on {} days, first {}, last {} (including the current batch)",
+            entries.len(),
+            days.len(),
+            local(first),
+            local(last)
+        );
+        let mut pages: HashMap<String, usize> = HashMap::new();
+        for entry in &entries {
+            *pages.entry(normalize_url(&entry.url)).or_default() += 1;
+        }
+        let mut pages: Vec<(String, usize)> = pages.into_iter().collect();
+        pages.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        out.push_str("Most requested pages:\n");
+        for (url, count) in pages.iter().take(TOP_PAGES) {
+            let _ = writeln!(out, "- {url} ×{count}");
+        }
+    }
+
+    // Days whose raw entries `gc` already rolled up
+    let first_raw = entries
+        .iter()
+        .map(|e| e.ts.with_timezone(&Local).date_naive())
+        .min();
+    let mut earlier_days = 0;
+    let mut earlier_requests = 0;
+    let mut earliest = None;
+    for day in storage::read_daily()?.into_values() {
+        if since.is_some_and(|s| day.date < s.with_timezone(&Local).date_naive())
+            || first_raw.is_some_and(|first| day.date >= first)
+        {
+            continue;
+        }
+        let requests: usize = day
+            .domains
+            .iter()
+            .filter(|(host, _)| in_domain(host, &domain))
+            .map(|(_, n)| n)
+            .sum();
+        if requests > 0 {
+            earlier_days += 1;
+            earlier_requests += requests;
+            earliest = earliest.or(Some(day.date));
+        }
+    }
+    if let Some(earliest) = earliest {
+        let _ = writeln!(
+            out,
+            "Before that (daily totals only): {earlier_requests} requests on {earlier_days} days since {earliest}"
+        );
+    }
+
+    if out.is_empty() {
+        out = format!("No visits to {domain}{window} recorded; this looks like a new interest.");
+    }
+    Ok(out)
+}
+
+/// The `n` most recent saved summaries for `persona`, newest first.
+pub fn previous_summaries(persona: Option<&str>, n: usize) -> Result<String> {
+    let mut history: Vec<_> = read_summary_history()?
+        .into_iter()
+        .filter(|s| s.persona.as_deref() == persona)
+        .collect();
+    history.sort_by_key(|s| std::cmp::Reverse(s.updated));
+    if history.is_empty() {
+        return Ok("No previous summaries.".to_string());
+    }
+    let mut out = String::new();
+    for summary in history.iter().take(n.clamp(1, MAX_SUMMARIES)) {
+        let _ = writeln!(
+            out,
+            "## {}\n{}\n",
+            local(summary.updated),
+            summary.text.trim()
+        );
+    }
+    Ok(out)
+}
+
+fn extract_title(html: &str) -> Option<String> {
+    let selector = Selector::parse("title").ok()?;
+    let title = Html::parse_document(html)
+        .select(&selector)
+        .next()?
+        .text()
+        .collect::<String>();
+    let title = title.split_whitespace().collect::<Vec<_>>().join(" ");
+    (!title.is_empty()).then_some(title)
+}
+
+/// A page's title and content type, reading only the start of the page.
+/// Non-HTML resources are identified from a HEAD request alone.
+pub async fn page_title(url: &str) -> Result<String> {
+    let client = reqwest::Client::builder().timeout(TITLE_TIMEOUT).build()?;
+    let content_type = |response: &reqwest::Response| {
+        response
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default()
+            .to_string()
+    };
+
+    // Some servers reject HEAD, so only trust it when it succeeds
+    if let Ok(head) = client.head(url).send().await {
+        let kind = content_type(&head);
+        if head.status().is_success() && !kind.is_empty() && !kind.contains("html") {
+            return Ok(format!("{url}: {} {kind}, not a web page", head.status()));
+        }
+    }
+
+    let mut response = client.get(url).send().await?;
+    let status = response.status();
+    let kind = content_type(&response);
+    let mut html = Vec::new();
+    while let Some(chunk) = response.chunk().await? {
+        html.extend_from_slice(&chunk);
+        if html.len() >= TITLE_READ_LIMIT
+            || String::from_utf8_lossy(&html)
+                .to_ascii_lowercase()
+                .contains("</title>")
+        {
+            break;
+        }
+    }
+    let title = extract_title(&String::from_utf8_lossy(&html));
+    Ok(format!(
+        "{url}: {status} {kind}\nTitle: {}",
+        title.as_deref().unwrap_or("(none)")
+    ))
+}